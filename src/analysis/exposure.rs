@@ -0,0 +1,206 @@
+use itertools::Itertools;
+use rand::Rng;
+use std::collections::HashSet;
+
+use crate::{common::Address, manager::Manager, node::NodeRole};
+
+/// What a coalition of compromised nodes learns
+#[derive(Debug, Default, PartialEq)]
+pub struct Exposure {
+    /// Honest contributors whose raw value can be reconstructed
+    pub contributors: Vec<Address>,
+    /// Aggregator groups whose partial aggregate can be reconstructed
+    pub aggregates: Vec<Vec<Address>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExposureEstimate {
+    pub compromised_fraction: f64,
+    pub draws: usize,
+    /// Average fraction of honest contributors whose value is exposed
+    pub exposed_contributors: f64,
+    /// Probability that at least one honest contributor is exposed
+    pub any_contributor_exposed: f64,
+    /// Average fraction of aggregator groups whose partial aggregate is exposed
+    pub exposed_aggregates: f64,
+}
+
+/// Computes what the compromised nodes learn, based on the current state of the tree.
+/// A value is exposed when the coalition holds all of its shares, either because it
+/// controls every member of the group that split it or every member of the group receiving it.
+/// Compromised contributors give their value away, they are not counted as exposed.
+pub fn exposure(manager: &Manager, compromised: &HashSet<Address>) -> Exposure {
+    let mut result = Exposure::default();
+    let all_compromised =
+        |addresses: &Vec<Address>| addresses.iter().all(|addr| compromised.contains(addr));
+
+    for address in manager.nodes.keys().copied().sorted() {
+        let tree_node = &manager.nodes.get(&address).unwrap().data().tree_node;
+        let role = manager.nodes.get(&address).unwrap().data().role;
+
+        if role == NodeRole::Contributor {
+            if !compromised.contains(&address) && all_compromised(&tree_node.parents) {
+                result.contributors.push(address);
+            }
+        } else if (role == NodeRole::Aggregator || role == NodeRole::LeafAggregator)
+            && tree_node.members.first() == Some(&address)
+            && (all_compromised(&tree_node.members) || all_compromised(&tree_node.parents))
+        {
+            result.aggregates.push(tree_node.members.clone());
+        }
+    }
+
+    result
+}
+
+/// Estimates the exposure when each node, except the querier, is compromised with a given probability
+pub fn estimate_exposure<R: Rng>(
+    manager: &Manager,
+    compromised_fraction: f64,
+    draws: usize,
+    rng: &mut R,
+) -> ExposureEstimate {
+    let mut addresses = manager
+        .nodes
+        .values()
+        .filter(|node| node.data().role != NodeRole::Querier)
+        .map(|node| (node.data().address, node.data().role))
+        .collect::<Vec<_>>();
+    addresses.sort_by_key(|(address, _)| *address);
+    let groups = addresses
+        .iter()
+        .filter(|(address, role)| {
+            (*role == NodeRole::Aggregator || *role == NodeRole::LeafAggregator)
+                && manager
+                    .nodes
+                    .get(address)
+                    .unwrap()
+                    .data()
+                    .tree_node
+                    .members
+                    .first()
+                    == Some(address)
+        })
+        .count();
+
+    let mut estimate = ExposureEstimate {
+        compromised_fraction,
+        draws,
+        ..Default::default()
+    };
+    for _ in 0..draws {
+        let compromised = addresses
+            .iter()
+            .filter(|_| rng.gen_bool(compromised_fraction))
+            .map(|(address, _)| *address)
+            .collect::<HashSet<_>>();
+        let exposure = exposure(manager, &compromised);
+
+        let honest = addresses
+            .iter()
+            .filter(|(address, role)| {
+                *role == NodeRole::Contributor && !compromised.contains(address)
+            })
+            .count();
+        if honest > 0 {
+            estimate.exposed_contributors += exposure.contributors.len() as f64 / honest as f64;
+        }
+        if !exposure.contributors.is_empty() {
+            estimate.any_contributor_exposed += 1.0;
+        }
+        if groups > 0 {
+            estimate.exposed_aggregates += exposure.aggregates.len() as f64 / groups as f64;
+        }
+    }
+    estimate.exposed_contributors /= draws as f64;
+    estimate.any_contributor_exposed /= draws as f64;
+    estimate.exposed_aggregates /= draws as f64;
+
+    estimate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compromised_leaf_group_exposes_its_contributors() {
+        let mut manager = Manager::default();
+        manager.setup();
+
+        let leaf_group = manager.groups_at_depth(1)[0].clone();
+        let contributors = manager
+            .nodes
+            .get(&leaf_group[0])
+            .unwrap()
+            .data()
+            .tree_node
+            .children
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+
+        // All but one member: nothing is learnt
+        let mut compromised = leaf_group[1..].iter().copied().collect::<HashSet<_>>();
+        assert_eq!(exposure(&manager, &compromised), Exposure::default());
+
+        compromised.insert(leaf_group[0]);
+        let result = exposure(&manager, &compromised);
+        assert_eq!(result.contributors, contributors);
+        assert_eq!(result.aggregates, vec![leaf_group]);
+    }
+
+    #[test]
+    fn estimate_bounds() {
+        let mut manager = Manager::default();
+        manager.setup();
+        let mut rng = manager.rng.clone();
+
+        let none = estimate_exposure(&manager, 0.0, 10, &mut rng);
+        assert_eq!(none.exposed_contributors, 0.0);
+        assert_eq!(none.any_contributor_exposed, 0.0);
+
+        // No honest contributor is left to expose
+        let all = estimate_exposure(&manager, 1.0, 10, &mut rng);
+        assert_eq!(all.exposed_contributors, 0.0);
+        assert_eq!(all.exposed_aggregates, 1.0);
+    }
+
+    #[test]
+    fn estimate_without_groups() {
+        // No tree has been built yet
+        let manager = Manager::default();
+        let mut rng = manager.rng.clone();
+
+        let estimate = estimate_exposure(&manager, 1.0, 10, &mut rng);
+        assert_eq!(estimate.exposed_aggregates, 0.0);
+        assert_eq!(estimate.exposed_contributors, 0.0);
+    }
+
+    #[test]
+    fn compromised_contributor_is_not_exposed() {
+        let mut manager = Manager::default();
+        manager.setup();
+
+        let leaf_group = manager.groups_at_depth(1)[0].clone();
+        let contributors = manager.nodes[&leaf_group[0]]
+            .data()
+            .tree_node
+            .children
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+
+        // The coalition only learns the value of a contributor it controls
+        let compromised = HashSet::from([contributors[0]]);
+        assert_eq!(exposure(&manager, &compromised), Exposure::default());
+
+        // With its parents, it learns the values of the honest contributors only
+        let mut compromised = leaf_group.iter().copied().collect::<HashSet<_>>();
+        compromised.insert(contributors[0]);
+        let result = exposure(&manager, &compromised);
+        assert_eq!(result.contributors, contributors[1..].to_vec());
+    }
+}
@@ -1,21 +1,72 @@
-#![feature(total_cmp)] // Needed to compare floats
-#![feature(derive_default_enum)]
-
+mod analysis;
 mod common;
 mod manager;
 mod message;
 mod node;
+mod overlay;
+mod population;
 mod run;
+mod scenario;
 mod shares;
+mod topology;
 mod tree_node;
 
-use manager::Manager;
+use manager::{BudgetLedger, Manager};
+use population::Population;
+use scenario::Scenario;
+use topology::Topology;
 
 fn main() {
+    // Repeated queries share the privacy budget ledger and the device population
+    let mut ledger = BudgetLedger::default();
+    let mut population = None;
+
+    // The number of rounds is given by the settings of the runs
+    let mut round = 0;
+    loop {
+        let rounds = run_query(round, &mut ledger, &mut population);
+        round += 1;
+        if round >= rounds {
+            break;
+        }
+    }
+}
+
+/// Runs a query and returns the number of rounds required by its settings
+fn run_query(
+    round: usize,
+    ledger: &mut BudgetLedger,
+    population: &mut Option<Population>,
+) -> usize {
     let mut manager = Manager::default();
+    manager.ledger = std::mem::take(ledger);
+    manager.population = population.take();
+    manager.recording.round = round;
+
+    // An optional topology file can be given with `--topology <path>`
+    let (scenario_path, topology_path) = parse_args(std::env::args().skip(1));
+    if let Some(path) = topology_path {
+        let topology = Topology::from_path(path.as_str())
+            .and_then(|topology| topology.validate().map(|_| topology));
+        match topology {
+            Ok(topology) => manager.topology = Some(topology),
+            Err(err) => {
+                eprintln!("Failed loading topology: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
 
     manager.setup();
 
+    // An optional scenario file can be given as argument
+    if let Some(path) = scenario_path {
+        match Scenario::from_path(path.as_str()) {
+            Ok(scenario) => manager.schedule_scenario(&scenario),
+            Err(err) => println!("Failed loading scenario: {}", err),
+        }
+    }
+
     manager
         .nodes
         .get(&manager.querier_address)
@@ -24,28 +75,60 @@ fn main() {
         .tree_node
         .print(&manager, Some(0));
 
-    let mut done = false;
-    while !done {
-        manager.handle_next_message();
+    manager.run();
 
-        let msg = manager.message_queue.last();
-        if msg.is_none() {
-            done = true;
-        }
+    manager.record_contributors();
+
+    manager.settle_budgets();
+
+    // Privacy exposure of the tree as it is at the end of the run
+    if manager.settings.collusion.compromised_fraction > 0.0 {
+        let mut rng = manager.rng.clone();
+        manager.recording.exposure = Some(analysis::estimate_exposure(
+            &manager,
+            manager.settings.collusion.compromised_fraction,
+            manager.settings.collusion.draws,
+            &mut rng,
+        ));
     }
 
-    manager.recording.final_contributors = manager
-        .nodes
-        .iter()
-        .filter(|(_, node)| node.data().death_time > manager.current_time)
-        .count();
-
-    if let Err(err) = manager.recording.write_to_path(
-        format!("{}.csv", chrono::offset::Utc::now())
-            .replace(":", "_")
-            .replace(" ", ".")
-            .as_str(),
-    ) {
+    let filename = format!("{}", chrono::offset::Utc::now())
+        .replace(":", "_")
+        .replace(" ", ".");
+    if let Err(err) = manager
+        .recording
+        .write_to_path(format!("{}.csv", filename).as_str())
+    {
         println!("Failed writing records: {}", err);
     }
+    if let Err(err) = manager
+        .export_topology()
+        .write_to_path(format!("{}.json", filename).as_str())
+    {
+        println!("Failed writing topology: {}", err);
+    }
+
+    *ledger = manager.ledger;
+    *population = manager.population;
+
+    manager
+        .settings
+        .budget
+        .map_or(1, |budget| budget.rounds.max(1))
+}
+
+/// Splits the command line into the scenario path and the `--topology` path
+fn parse_args(mut args: impl Iterator<Item = String>) -> (Option<String>, Option<String>) {
+    let mut scenario = None;
+    let mut topology = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--topology" => match args.next() {
+                Some(path) => topology = Some(path),
+                None => println!("Missing path after --topology"),
+            },
+            _ => scenario = Some(arg),
+        }
+    }
+    (scenario, topology)
 }
@@ -0,0 +1,254 @@
+use crate::{
+    common::Address,
+    message::{Message, MessageType},
+    node::{Partition, PartitionMode},
+};
+
+use super::Manager;
+
+/// Fault injection, every fault takes effect through an event handled by the targeted nodes.
+/// Faults scheduled in the past take effect right away, the simulation clock never goes back.
+impl Manager {
+    /// Kills a node at the given time
+    pub fn kill_node(&mut self, address: Address, at: f64) {
+        self.insert_message(Message::new_timeout(
+            MessageType::NodeFailure,
+            address,
+            self.current_time,
+            at.max(self.current_time),
+        ));
+    }
+
+    /// Adds a delay to every message sent by a node from the given time, on top of its current
+    /// delay, a negative delay lifts a previous one
+    pub fn delay_node(&mut self, address: Address, delay: f64, at: f64) {
+        let mut msg = Message::new_timeout(
+            MessageType::SetLinkDelay,
+            address,
+            self.current_time,
+            at.max(self.current_time),
+        );
+        msg.content.delay = Some(delay);
+        self.insert_message(msg);
+    }
+
+    /// Multiplies the costs and the latency of a node by a factor from the given time
+    pub fn slow_down(&mut self, address: Address, factor: f64, at: f64) {
+        let mut msg = Message::new_timeout(
+            MessageType::SlowDown,
+            address,
+            self.current_time,
+            at.max(self.current_time),
+        );
+        msg.content.factor = Some(factor);
+        self.insert_message(msg);
+    }
+
+    /// Sets the latency of the link between two nodes, in both directions, from the given time
+    pub fn set_link_latency(&mut self, a: Address, b: Address, latency: f64, at: f64) {
+        for (node, peer) in [(a, b), (b, a)] {
+            let mut msg = Message::new_timeout(
+                MessageType::SetLinkLatency,
+                node,
+                self.current_time,
+                at.max(self.current_time),
+            );
+            msg.content.target_node = Some(peer);
+            msg.content.latency = Some(latency);
+            self.insert_message(msg);
+        }
+    }
+
+    /// Drops or holds back the messages exchanged between two sets of nodes during the given period
+    pub fn partition(
+        &mut self,
+        set_a: &[Address],
+        set_b: &[Address],
+        from: f64,
+        to: f64,
+        mode: PartitionMode,
+    ) {
+        for (nodes, peers) in [(set_a, set_b), (set_b, set_a)] {
+            for &node in nodes {
+                let mut msg = Message::new_timeout(
+                    MessageType::StartPartition,
+                    node,
+                    self.current_time,
+                    from.max(self.current_time),
+                );
+                msg.content.peers = peers.to_vec();
+                msg.content.partition = Some(Partition::new(to, mode));
+                self.insert_message(msg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_manager() -> Manager {
+        let mut manager = Manager::default();
+        manager.setup();
+        // The run is left out, faults are scheduled on idle nodes from time 0
+        manager.message_queue.clear();
+        manager.current_time = 0.0;
+        manager
+    }
+
+    #[test]
+    fn kill_node() {
+        let mut manager = setup_manager();
+
+        manager.kill_node(1, 500.0);
+        manager.insert_message(Message::new(MessageType::RequestHealth, 600.0, 2, 700.0, 1));
+        manager.run();
+
+        assert_eq!(manager.nodes.get(&1).unwrap().data().death_time, 500.0);
+        assert!(!manager.recording.sent_messages.last().unwrap().delivered);
+    }
+
+    #[test]
+    fn past_faults_take_effect_now() {
+        let mut manager = Manager::default();
+        manager.setup();
+        let now = manager.current_time;
+
+        manager.kill_node(1, now - 100.0);
+        manager.slow_down(2, 2.0, 0.0);
+        assert!(manager
+            .message_queue
+            .iter()
+            .all(|msg| msg.arrival_time >= now));
+
+        let mut time = now;
+        while manager.handle_next_message() {
+            assert!(manager.current_time >= time);
+            time = manager.current_time;
+        }
+        assert_eq!(manager.nodes[&1].data().death_time, now);
+    }
+
+    #[test]
+    fn partition_drops_messages() {
+        let mut manager = setup_manager();
+
+        manager.partition(&[1], &[2, 3], 500.0, 1000.0, PartitionMode::Drop);
+        for arrival_time in [800.0, 1200.0] {
+            manager.insert_message(Message::new(
+                MessageType::RequestHealth,
+                arrival_time - 100.0,
+                2,
+                arrival_time,
+                1,
+            ));
+        }
+        manager.run();
+
+        let health_checks = manager
+            .recording
+            .sent_messages
+            .iter()
+            .filter(|msg| msg.message_type == MessageType::RequestHealth)
+            .map(|msg| msg.delivered)
+            .collect::<Vec<_>>();
+        assert_eq!(health_checks, vec![false, true]);
+    }
+
+    #[test]
+    fn partition_holds_messages() {
+        let mut manager = setup_manager();
+
+        manager.partition(&[1], &[2], 500.0, 1000.0, PartitionMode::Hold);
+        manager.insert_message(Message::new(MessageType::RequestHealth, 700.0, 2, 800.0, 1));
+        manager.run();
+
+        let health_check = manager
+            .recording
+            .sent_messages
+            .iter()
+            .find(|msg| msg.message_type == MessageType::RequestHealth)
+            .unwrap();
+        assert!(health_check.delivered);
+        assert_eq!(health_check.arrival_time, 1000.0);
+    }
+
+    #[test]
+    fn partition_causes_false_suspicions() {
+        let mut manager = setup_manager();
+
+        // Node #1 monitors its children
+        let children = manager
+            .nodes
+            .get(&1)
+            .unwrap()
+            .data()
+            .tree_node
+            .children
+            .iter()
+            .map(|group| group[0])
+            .collect::<Vec<_>>();
+        manager.partition(&[1], &children, 0.0, 3000.0, PartitionMode::Drop);
+        manager.insert_message(Message::new_timeout(
+            MessageType::ScheduleHealthCheck,
+            1,
+            0.0,
+            1000.0,
+        ));
+        while manager.current_time < 2500.0 && manager.handle_next_message() {}
+
+        assert_eq!(manager.recording.suspicions, children.len());
+        assert_eq!(manager.recording.false_suspicions, children.len());
+    }
+
+    #[test]
+    fn partition_heals() {
+        let mut manager = setup_manager();
+
+        let children = manager.nodes[&1]
+            .data()
+            .tree_node
+            .children
+            .iter()
+            .map(|group| group[0])
+            .collect::<Vec<_>>();
+        manager.partition(&[1], &children, 0.0, 2500.0, PartitionMode::Drop);
+        manager.insert_message(Message::new_timeout(
+            MessageType::ScheduleHealthCheck,
+            1,
+            0.0,
+            1000.0,
+        ));
+        while manager.current_time < 5500.0 && manager.handle_next_message() {}
+
+        // The children are monitored again once the partition healed
+        let node = manager.nodes[&1].data();
+        assert!(node.suspects.is_empty());
+        assert!(children.iter().all(|child| node
+            .opened_channels
+            .iter()
+            .any(|channel| channel.peer_address == *child && channel.maintained)));
+        assert_eq!(manager.recording.suspicions, children.len());
+    }
+
+    #[test]
+    fn link_latency_and_slow_down() {
+        let mut manager = setup_manager();
+
+        manager.set_link_latency(1, 2, 500.0, 0.0);
+        manager.slow_down(1, 2.0, 0.0);
+        manager.insert_message(Message::new(MessageType::RequestHealth, 0.0, 2, 100.0, 1));
+        manager.insert_message(Message::new(MessageType::RequestHealth, 0.0, 3, 100.0, 1));
+        manager.run();
+
+        let confirmations = manager
+            .recording
+            .sent_messages
+            .iter()
+            .filter(|msg| msg.message_type == MessageType::ConfirmHealth)
+            .map(|msg| (msg.receiver, msg.arrival_time - msg.departure_time))
+            .collect::<Vec<_>>();
+        assert_eq!(confirmations, vec![(3, 200.0), (2, 500.0)]);
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use crate::{
+    common::Address,
+    node::{NodeData, NodeRole},
+};
+
+use super::Manager;
+
+/// Privacy budget spent by each contributor, kept across the rounds
+#[derive(Clone, Debug, Default)]
+pub struct BudgetLedger {
+    pub spent: HashMap<Address, f64>,
+}
+
+impl BudgetLedger {
+    pub fn remaining(&self, address: Address, budget: f64) -> f64 {
+        budget - self.spent.get(&address).unwrap_or(&0.0)
+    }
+
+    pub fn charge(&mut self, address: Address, epsilon: f64) {
+        *self.spent.entry(address).or_insert(0.0) += epsilon;
+    }
+}
+
+/// Budgets belong to the devices when nodes are sampled from a population
+fn budget_holder(address: Address, data: &NodeData) -> usize {
+    data.device.as_ref().map_or(address, |device| device.id)
+}
+
+impl Manager {
+    /// Gives each contributor the budget it has left from the previous rounds
+    pub(super) fn initialize_budgets(&mut self) {
+        let budget = match &self.settings.budget {
+            Some(budget) => budget.budget,
+            None => return,
+        };
+
+        for (address, node) in self.nodes.iter_mut() {
+            if node.data().role == NodeRole::Contributor {
+                let holder = budget_holder(*address, node.data());
+                node.data_mut().remaining_budget = Some(self.ledger.remaining(holder, budget));
+            }
+        }
+    }
+
+    /// Charges the ledger with the budget spent during the round
+    pub fn settle_budgets(&mut self) {
+        for (address, node) in self.nodes.iter() {
+            if node.data().spent_budget > 0.0 {
+                let holder = budget_holder(*address, node.data());
+                self.ledger.charge(holder, node.data().spent_budget);
+            }
+            if node.data().declined {
+                self.recording.refusals += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        manager::Outcome,
+        run::{BudgetPolicy, BudgetSettings},
+    };
+
+    use super::*;
+
+    #[test]
+    fn exhausted_budgets_are_refused() {
+        let mut ledger = BudgetLedger::default();
+        let mut results = vec![];
+
+        for round in 0..2 {
+            let mut manager = Manager::default();
+            manager.settings.budget = Some(BudgetSettings {
+                budget: 1.0,
+                query_cost: 1.0,
+                policy: BudgetPolicy::Refuse,
+                rounds: 2,
+            });
+            manager.ledger = ledger;
+            manager.recording.round = round;
+
+            manager.setup();
+            manager.run();
+            manager.settle_budgets();
+
+            results.push((
+                manager.recording.outcome(),
+                manager.recording.refusals,
+                manager.recording.initial_contributors,
+            ));
+            ledger = manager.ledger;
+        }
+
+        assert_eq!(results[0].0, Outcome::Complete);
+        assert_eq!(results[0].1, 0);
+        // Every contributor spent its budget during the first round
+        assert_eq!(results[1].0, Outcome::Incomplete);
+        assert_eq!(results[1].1, results[1].2);
+    }
+}
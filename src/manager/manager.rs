@@ -5,11 +5,17 @@ use sorted_insert::SortedInsertBinary;
 use std::collections::HashMap;
 
 use crate::common::*;
-use crate::message::Message;
-use crate::node::{Node, QuerierNode};
-use crate::run::{BuildingBlocks, CostsSettings, RunSettings, TreeSettings};
+use crate::message::{Message, MessageType};
+use crate::node::{Node, NodeRole, QuerierNode};
+use crate::overlay::Chord;
+use crate::population::Population;
+use crate::run::{
+    BuildingBlocks, ByzantineSettings, CollusionSettings, ConstructionMode, CostsSettings,
+    DiscoveryMode, OmissionSettings, RunSettings, StragglerSettings, TreeSettings,
+};
+use crate::topology::Topology;
 
-use super::Recording;
+use super::{BudgetLedger, Recording};
 
 pub struct Manager {
     pub settings: RunSettings,
@@ -19,6 +25,15 @@ pub struct Manager {
     pub current_time: f64,
     pub rng: SmallRng,
     pub recording: Recording,
+    pub ledger: BudgetLedger,
+    /// Channel openings of the tree construction that are not settled yet
+    pub pending_handshakes: usize,
+    /// Tree to load instead of generating one
+    pub topology: Option<Topology>,
+    /// Devices shared by the queries, generated from the settings when missing
+    pub population: Option<Population>,
+    /// Overlay in which the nodes look each other up
+    pub overlay: Option<Chord>,
 }
 
 impl Manager {
@@ -30,6 +45,7 @@ impl Manager {
                 fanout: 4,
                 depth: 3,
                 group_size: 3,
+                ..TreeSettings::default()
             },
         )
     }
@@ -48,11 +64,25 @@ impl Manager {
                 comm: 100.0,
                 compute: 0.0,
             },
+            stragglers: StragglerSettings::default(),
+            omissions: OmissionSettings::default(),
+            byzantines: ByzantineSettings::default(),
+            collusion: CollusionSettings::default(),
+            noise: None,
+            budget: None,
+            targeting: None,
+            release_threshold: 0,
+            required_contributions: None,
+            collection_deadlines: vec![],
+            progressive_release: None,
+            construction: ConstructionMode::Estimated,
+            discovery: DiscoveryMode::Direct,
+            population: None,
             tree,
             seed,
         };
 
-        let manager = Manager {
+        Manager {
             settings: settings.clone(),
             querier_address: 0_usize,
             nodes: HashMap::new(),
@@ -60,38 +90,87 @@ impl Manager {
             current_time: 0.0,
             rng: SmallRng::from_seed(seed_bytes),
             recording: Recording::new(settings, true),
-        };
-
-        manager
+            ledger: BudgetLedger::default(),
+            pending_handshakes: 0,
+            topology: None,
+            population: None,
+            overlay: None,
+        }
     }
 
     /// Creates all the nodes in the tree and initializes them
     pub fn setup(&mut self) {
-        // Create the querier group
-        let mut querier_group: Box<dyn Node> =
+        // Settings may have been changed since the manager was created
+        if let Err(err) = self.settings.validate() {
+            panic!("Invalid settings: {}", err);
+        }
+        self.recording.settings = self.settings.clone();
+
+        match self.topology.clone() {
+            Some(topology) => {
+                if let Err(err) = self.load_topology(&topology) {
+                    panic!("Failed loading topology: {}", err);
+                }
+            }
+            None => self.create_tree(),
+        }
+
+        self.current_time = match self.settings.construction {
+            ConstructionMode::Estimated => self.settings.tree_construction_latency(),
+            ConstructionMode::Simulated => 0.0,
+        };
+        self.recording.construction_latency = self.current_time;
+
+        self.assign_devices();
+        self.generate_failures();
+        self.generate_stragglers();
+        self.generate_omissions();
+        self.generate_byzantines();
+        self.generate_noise();
+        self.initialize_budgets();
+        self.generate_attributes();
+        self.initialize_quotas();
+        self.build_overlay();
+
+        // Only the contributors targeted by the query take part in the result
+        let targeted = self
+            .nodes
+            .values()
+            .filter(|node| node.data().role == NodeRole::Contributor)
+            .filter(|node| self.settings.targets(&node.data().attributes))
+            .map(|node| node.data().secret_value)
+            .collect::<Vec<_>>();
+        self.recording.targeted_contributors = targeted.len();
+        self.recording.expected_result = targeted.iter().sum();
+
+        self.initialize_nodes();
+
+        self.schedule_failures();
+    }
+
+    /// Generates the querier and the tree below it
+    fn create_tree(&mut self) {
+        // The querier is a single node, reconstructing the result from the shares of its children
+        let mut querier: Box<dyn Node> =
             QuerierNode::new(self.settings.clone(), self.querier_address);
-        querier_group.data_mut().tree_node.members = (0..self.settings.tree.group_size)
-            .map(|_| self.querier_address)
-            .collect();
-        self.nodes.insert(self.querier_address, querier_group);
+        querier.data_mut().tree_node.members = vec![self.querier_address];
+        self.nodes.insert(self.querier_address, querier);
 
         // Create the tree below the querier
+        let mut contributors = self.contributors_per_leaf().into_iter();
         Manager::create_tree_node(
             self,
             self.querier_address,
             self.settings.tree.depth,
             self.settings.tree.depth,
             self.querier_address.increment(None),
+            &mut contributors,
         );
+    }
 
-        self.current_time = self.settings.tree_construction_latency();
-
-        self.generate_failures();
-
-        // Initialize the tree with the failures that occured during tree creation
-        // self.initialize_tree_failures();
-
-        self.initialize_nodes();
+    /// Handles the messages until none is left
+    pub fn run(&mut self) {
+        while self.handle_next_message() {}
     }
 
     pub fn handle_next_message(&mut self) -> bool {
@@ -101,9 +180,12 @@ impl Manager {
             // Move simulation clock
             self.current_time = msg.arrival_time;
 
-            if (self.current_time > 100000.0) {
-                // Prevent inifinite loops
-                panic!("Deadline hit...");
+            if self.current_time > 100000.0 {
+                // Prevent inifinite loops, the protocol is stuck
+                println!("Deadline hit...");
+                self.record_aggregation();
+                self.message_queue.clear();
+                return false;
             }
 
             let resulting_messages = self
@@ -112,17 +194,67 @@ impl Manager {
                 .unwrap()
                 .handle_message(&mut msg);
 
-            if resulting_messages.is_none() {
-                // Message bounced, queue it back
-                self.insert_message(msg);
-            } else {
+            if let Some(resulting_messages) = resulting_messages {
                 self.current_time = msg.arrival_time;
+                if msg.message_type == MessageType::NodeFailure && msg.delivered {
+                    self.cancel_timers(msg.receiver);
+                }
+                if msg.message_type == MessageType::ReleaseResult && msg.delivered {
+                    self.recording
+                        .record_release(msg.arrival_time, msg.content.data.as_ref().unwrap());
+                }
+                if msg.message_type == MessageType::Stop
+                    && msg.delivered
+                    && msg.emitter == self.querier_address
+                {
+                    // Only the querier ends the run
+                    if let Some(result) = &msg.content.data {
+                        self.recording.record_release(msg.arrival_time, result);
+                    }
+                    self.recording.result = msg.content.data.clone();
+                    self.record_aggregation();
+                    self.message_queue.clear();
+                }
+                if msg.message_type == MessageType::SuspectFailure && msg.delivered {
+                    let suspect = self
+                        .nodes
+                        .get(&msg.content.target_node.unwrap())
+                        .unwrap()
+                        .data();
+                    let alive = suspect.death_time > msg.arrival_time;
+                    let straggler = suspect.slowdown > 1.0;
+                    self.recording.record_suspicion(alive, straggler);
+                    if suspect.address == self.querier_address && !alive {
+                        // The failure of the querier is confirmed, the query is abandoned
+                        println!("[@{}] The query is abandoned", msg.arrival_time);
+                        self.record_aggregation();
+                        self.message_queue.clear();
+                    }
+                }
+                if msg.message_type == MessageType::ReplaceNode && msg.delivered {
+                    self.replace_node(msg.content.target_node.unwrap(), msg.arrival_time);
+                }
+                if msg.message_type == MessageType::ElectLeader && msg.delivered {
+                    let former_leader = self
+                        .nodes
+                        .get(&msg.content.target_node.unwrap())
+                        .unwrap()
+                        .data();
+                    // Falsely suspected leaders are replaced right away
+                    self.recording
+                        .record_election((msg.arrival_time - former_leader.death_time).max(0.0));
+                }
+                if self.pending_handshakes > 0 {
+                    self.track_construction(&msg, &resulting_messages);
+                }
                 resulting_messages
-                    .unwrap()
                     .iter()
                     .sorted()
                     .for_each(|resulting_message| self.insert_message(resulting_message.clone()));
                 self.recording.record(&msg);
+            } else {
+                // Message bounced, queue it back
+                self.insert_message(msg);
             }
             true
         } else {
@@ -133,11 +265,107 @@ impl Manager {
     pub fn insert_message(&mut self, msg: Message) {
         self.message_queue.sorted_insert_asc_binary(msg);
     }
+
+    /// Records which contributors are alive at the end of the run and which of them are missing
+    /// from the result
+    pub fn record_contributors(&mut self) {
+        let included = self
+            .recording
+            .result
+            .as_ref()
+            .map(|result| result.contributors.clone())
+            .unwrap_or_default();
+        let alive = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.data().role == NodeRole::Contributor)
+            .filter(|(_, node)| node.data().death_time > self.current_time)
+            .map(|(address, _)| *address)
+            .sorted()
+            .collect::<Vec<_>>();
+
+        self.recording.final_contributors = alive.len();
+        self.recording.excluded_contributors = alive
+            .into_iter()
+            .filter(|address| !included.contains_key(address))
+            .collect();
+    }
+
+    /// Follows the channel openings of the tree construction, until none is left
+    fn track_construction(&mut self, msg: &Message, resulting_messages: &[Message]) {
+        self.pending_handshakes += resulting_messages
+            .iter()
+            .filter(|msg| msg.message_type == MessageType::OpenChannel || msg.started_lookup())
+            .count();
+
+        // An opening is settled once confirmed, or when it never reached its peer. A lookup is
+        // settled once answered, the opening it leads to taking over.
+        let settled = match msg.message_type {
+            MessageType::OpenChannel | MessageType::Lookup => !msg.delivered,
+            MessageType::ConfirmChannel | MessageType::LookupResponse => true,
+            _ => false,
+        };
+        if settled {
+            self.pending_handshakes -= 1;
+            if self.pending_handshakes == 0 {
+                self.finish_construction();
+            }
+        }
+    }
+
+    /// Counts the aggregates refused for falling below the release threshold, the groups whose
+    /// members included different children, the children left out at collection deadlines and
+    /// the contributors only partly received by the querier
+    fn record_aggregation(&mut self) {
+        let missing = self
+            .nodes
+            .values()
+            .map(|node| node.data().missing_children)
+            .filter(|&missing| missing > 0)
+            .collect::<Vec<_>>();
+        self.recording.expired_deadlines = missing.len();
+        self.recording.missing_children = missing.iter().sum();
+        self.recording.consistency_mismatches = self
+            .nodes
+            .values()
+            .filter(|node| node.data().detected_mismatch)
+            .count();
+        self.recording.rejected_aggregates = self
+            .nodes
+            .values()
+            .filter(|node| node.data().rejected_aggregate)
+            .count();
+        let querier = self.nodes.get(&self.querier_address).unwrap().data();
+        self.recording.result_rejected = querier.rejected_aggregate;
+        self.recording.partial_contributors = querier.partial_contributors;
+        self.recording.querier_failed = querier.death_time <= self.current_time;
+    }
+
+    /// Removes the pending timeouts of a node, e.g. its health checks, once it has failed
+    pub fn cancel_timers(&mut self, address: Address) {
+        self.message_queue
+            .retain(|msg| msg.emitter != address || msg.receiver != address);
+    }
+}
+
+#[cfg(test)]
+impl Manager {
+    /// Default manager, set up with the given settings
+    pub fn configured(configure: impl FnOnce(&mut RunSettings)) -> Manager {
+        let mut manager = Manager::default();
+        configure(&mut manager.settings);
+        manager.setup();
+        manager
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{message::MessageType, run::TreeSettings};
+    use crate::{
+        manager::Outcome,
+        run::TreeSettings,
+        shares::{AggregatableShares, Share},
+    };
 
     use super::*;
 
@@ -150,12 +378,13 @@ mod tests {
                 fanout: 4,
                 depth: 3,
                 group_size: 3,
+                ..TreeSettings::default()
             },
         );
         manager.setup();
         manager.message_queue.clear();
 
-        let arrival_time = 1000.0;
+        let arrival_time = 2000.0;
         let emitter: Address = 0;
         let receiver: Address = 0;
         manager.insert_message(Message::new(
@@ -175,6 +404,187 @@ mod tests {
         );
     }
 
+    #[test]
+    fn run_to_completion() {
+        let mut manager = Manager::default();
+        manager.setup();
+
+        manager.run();
+
+        assert_eq!(manager.recording.outcome(), Outcome::Complete);
+        assert_eq!(
+            manager.recording.result.as_ref().unwrap().share,
+            manager.recording.expected_result
+        );
+
+        manager.record_contributors();
+        assert_eq!(
+            manager.recording.included_contributors(),
+            manager.recording.initial_contributors
+        );
+        assert!(manager.recording.excluded_contributors.is_empty());
+    }
+
+    #[test]
+    fn query_dissemination() {
+        let mut manager = Manager::default();
+        manager.setup();
+        manager.run();
+
+        // Every node received the query before the contributors answered
+        assert!(manager
+            .nodes
+            .values()
+            .all(|node| node.data().query.is_some()));
+        let first_request = manager
+            .recording
+            .sent_messages
+            .iter()
+            .filter(|msg| msg.message_type == MessageType::RequestData)
+            .filter(|msg| manager.nodes[&msg.receiver].data().role == NodeRole::Contributor)
+            .map(|msg| msg.arrival_time)
+            .fold(f64::INFINITY, f64::min);
+        let hops = manager.settings.tree.depth as f64 + 1.0;
+        assert!(first_request >= manager.settings.tree_construction_latency() + hops * 100.0);
+    }
+
+    #[test]
+    fn release_threshold() {
+        let mut manager = Manager::default();
+        manager.settings.release_threshold = 1;
+        manager.setup();
+        manager.run();
+
+        assert_eq!(manager.recording.outcome(), Outcome::Complete);
+        assert_eq!(manager.recording.rejected_aggregates, 0);
+
+        // No subtree, nor the whole tree, holds enough contributions
+        let mut manager = Manager::default();
+        manager.settings.release_threshold = 1000;
+        manager.setup();
+        manager.run();
+
+        assert_eq!(manager.recording.outcome(), Outcome::Rejected);
+        assert_eq!(
+            manager.recording.rejected_aggregates,
+            manager.nodes.len() - manager.recording.initial_contributors
+        );
+    }
+
+    #[test]
+    fn partial_contributions_below_threshold() {
+        let mut manager = Manager::default();
+        manager.settings.release_threshold = 2;
+        manager.setup();
+        manager.message_queue.clear();
+
+        // Contributor #1000 is fully received, the others with a single share each
+        let members = manager.nodes[&1].data().tree_node.members.clone();
+        for (i, &member) in members.iter().enumerate() {
+            let mut msg = Message::new(
+                MessageType::SendData,
+                manager.current_time,
+                member,
+                manager.current_time + 100.0,
+                manager.querier_address,
+            );
+            msg.content.data =
+                Some(vec![Share::new(1.0, 1000), Share::new(1.0, 1001 + i)].aggregate());
+            manager.insert_message(msg);
+        }
+        manager.run();
+
+        // Six shares, but a single reconstructed contribution
+        assert_eq!(manager.recording.outcome(), Outcome::Rejected);
+        assert_eq!(manager.recording.partial_contributors, 3);
+    }
+
+    #[test]
+    fn partial_contributions_are_recorded() {
+        let mut manager = Manager::default();
+        manager.setup();
+        manager.message_queue.clear();
+
+        // Contributor #1000 is fully received, the others with a single share each
+        let members = manager.nodes[&1].data().tree_node.members.clone();
+        for (i, &member) in members.iter().enumerate() {
+            let mut msg = Message::new(
+                MessageType::SendData,
+                manager.current_time,
+                member,
+                manager.current_time + 100.0,
+                manager.querier_address,
+            );
+            msg.content.data =
+                Some(vec![Share::new(1.0, 1000), Share::new(1.0, 1001 + i)].aggregate());
+            manager.insert_message(msg);
+        }
+        manager.run();
+
+        // The released sum still holds the partial shares, which are reported alongside it
+        let result = manager.recording.result.as_ref().unwrap();
+        assert_eq!(result.contributors.len(), 1);
+        assert_eq!(result.share, 6.0);
+        assert_eq!(manager.recording.partial_contributors, 3);
+    }
+
+    #[test]
+    fn progressive_release() {
+        let mut manager = Manager::default();
+        manager.settings.progressive_release = Some(0.5);
+        manager.setup();
+        // Slower groups make the children of the top-level group answer at different times
+        let children = manager.nodes[&1].data().tree_node.children.clone();
+        for (i, group) in children.iter().enumerate() {
+            for member in group {
+                manager.nodes.get_mut(member).unwrap().data_mut().slowdown = 1.0 + i as f64;
+            }
+        }
+        manager.run();
+
+        assert_eq!(manager.recording.outcome(), Outcome::Complete);
+        // Provisional results are refined until the final one
+        let releases = &manager.recording.releases;
+        assert!(releases.len() > 1);
+        assert!(releases.windows(2).all(
+            |pair| pair[0].time <= pair[1].time && pair[0].contributors < pair[1].contributors
+        ));
+        let last = releases.last().unwrap();
+        assert_eq!(last.contributors, manager.recording.initial_contributors);
+        assert_eq!(last.error, 0.0);
+        assert!(releases[0].error > 0.0);
+    }
+
+    #[test]
+    fn node_failure_cancels_timers() {
+        let mut manager = Manager::default();
+        manager.setup();
+        manager.message_queue.clear();
+
+        let address: Address = 1;
+        manager.insert_message(Message::new_timeout(
+            MessageType::ScheduleHealthCheck,
+            address,
+            0.0,
+            2000.0,
+        ));
+        manager.insert_message(Message::new_timeout(
+            MessageType::NodeFailure,
+            address,
+            0.0,
+            1000.0,
+        ));
+
+        manager.handle_next_message();
+
+        assert_eq!(
+            manager.nodes.get(&address).unwrap().data().death_time,
+            1000.0
+        );
+        assert!(manager.message_queue.is_empty());
+        assert_eq!(manager.recording.failures, 1);
+    }
+
     #[test]
     fn test_message_insertion() {
         let mut manager = Manager::new(
@@ -184,6 +594,7 @@ mod tests {
                 fanout: 4,
                 depth: 3,
                 group_size: 3,
+                ..TreeSettings::default()
             },
         );
         manager.setup();
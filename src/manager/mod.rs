@@ -1,7 +1,13 @@
+mod faults;
+mod ledger;
+#[allow(clippy::module_inception)]
 mod manager;
 mod recording;
+mod replacement;
+mod scenario;
 mod setup;
+mod topology;
 
+pub use ledger::*;
 pub use manager::*;
 pub use recording::*;
-pub use setup::*;
@@ -1,9 +1,110 @@
+use itertools::Itertools;
 use std::{
     error::Error,
+    fmt,
     fs::{self, File},
 };
 
-use crate::{message::Message, run::RunSettings};
+use crate::{
+    analysis::ExposureEstimate,
+    common::Address,
+    message::{Message, MessageType},
+    run::RunSettings,
+    shares::Share,
+};
+
+const COLUMNS: &[&str] = &[
+    "seed",
+    "failure_handling",
+    "average_failure_time",
+    "health_check_period",
+    "communication_cost",
+    "crypto_cost",
+    "compute_cost",
+    "tree_depth",
+    "tree_fanout",
+    "group_size",
+    "total_work",
+    "total_latency",
+    "total_bandwidth",
+    "completeness",
+    "failures",
+    "suspicions",
+    "false_suspicions",
+    "replacements",
+    "unnecessary_replacements",
+    "stragglers",
+    "suspected_stragglers",
+    "omission_faulty",
+    "byzantines",
+    "outcome",
+    "expected_result",
+    "result",
+    "result_count",
+    "corrupted",
+    "tampering_detected",
+    "exposed_contributors",
+    "any_contributor_exposed",
+    "exposed_aggregates",
+    "epsilon",
+    "delta",
+    "accuracy_error",
+    "round",
+    "refusals",
+    "rejected_aggregates",
+    "consistency_mismatches",
+    "leader_elections",
+    "election_latency",
+    "construction_latency",
+    "construction_work",
+    "excluded_contributors",
+    "lookups",
+    "lookup_hops",
+    "targeted_contributors",
+    "selectivity",
+    "required_contributions",
+    "provisioning_overhead",
+    "cancelled_shares",
+    "expired_deadlines",
+    "missing_children",
+    "partial_contributors",
+    "release_times",
+    "release_contributors",
+    "release_errors",
+    "message_type",
+    "emitter_address",
+    "receiver_address",
+    "departure_time",
+    "arrival_time",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// The querier never received all the data
+    Hung,
+    /// The querier refused to release a result below the release threshold
+    Rejected,
+    /// The querier failed before releasing a result
+    QuerierFailed,
+    /// Some contributions are missing from the result
+    Incomplete,
+    Complete,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Result released by the querier, provisional or final
+#[derive(Debug)]
+pub struct Release {
+    pub time: f64,
+    pub contributors: usize,
+    /// Distance between the released value and the ground truth
+    pub error: f64,
+}
 
 #[derive(Debug)]
 pub struct Recording {
@@ -13,6 +114,48 @@ pub struct Recording {
     pub total_bandwidth: f64,
     pub initial_contributors: usize,
     pub final_contributors: usize,
+    /// Contributors matching the predicate of the query
+    pub targeted_contributors: usize,
+    /// Shares that contributors did not send, as enough contributions were secured
+    pub cancelled_shares: usize,
+    /// Nodes that forwarded their aggregate at their collection deadline, and the children
+    /// they left out
+    pub expired_deadlines: usize,
+    pub missing_children: usize,
+    /// Contributors with only some of their shares in the result, which still adds them up
+    pub partial_contributors: usize,
+    pub releases: Vec<Release>,
+    /// Contributors alive at the end of the run whose value is missing from the result
+    pub excluded_contributors: Vec<Address>,
+    pub failures: usize,
+    pub suspicions: usize,
+    pub false_suspicions: usize,
+    pub replacements: usize,
+    pub unnecessary_replacements: usize,
+    pub stragglers: usize,
+    pub suspected_stragglers: usize,
+    pub omission_faulty: usize,
+    pub byzantines: usize,
+    pub expected_result: f64,
+    pub result: Option<Share>,
+    pub exposure: Option<ExposureEstimate>,
+    pub required_noises: usize,
+    pub round: usize,
+    pub refusals: usize,
+    pub rejected_aggregates: usize,
+    pub result_rejected: bool,
+    pub querier_failed: bool,
+    pub consistency_mismatches: usize,
+    pub leader_elections: usize,
+    /// Time between the failures of the leaders and the elections of their replacements
+    pub election_latency: f64,
+    /// Time at which the tree is built and the aggregation starts
+    pub construction_latency: f64,
+    /// Work spent building the tree, when its construction is simulated
+    pub construction_work: f64,
+    /// Lookups started in the overlay, and the hops they took
+    pub lookups: usize,
+    pub lookup_hops: usize,
     pub sent_messages: Vec<Message>,
     pub full_export: bool,
 }
@@ -26,12 +169,55 @@ impl Recording {
             total_bandwidth: 0.0,
             initial_contributors: 0,
             final_contributors: 0,
+            targeted_contributors: 0,
+            cancelled_shares: 0,
+            expired_deadlines: 0,
+            missing_children: 0,
+            partial_contributors: 0,
+            releases: vec![],
+            excluded_contributors: vec![],
+            failures: 0,
+            suspicions: 0,
+            false_suspicions: 0,
+            replacements: 0,
+            unnecessary_replacements: 0,
+            stragglers: 0,
+            suspected_stragglers: 0,
+            omission_faulty: 0,
+            byzantines: 0,
+            expected_result: 0.0,
+            result: None,
+            exposure: None,
+            required_noises: 0,
+            round: 0,
+            refusals: 0,
+            rejected_aggregates: 0,
+            result_rejected: false,
+            querier_failed: false,
+            consistency_mismatches: 0,
+            leader_elections: 0,
+            election_latency: 0.0,
+            construction_latency: 0.0,
+            construction_work: 0.0,
+            lookups: 0,
+            lookup_hops: 0,
             sent_messages: vec![],
             full_export,
         }
     }
 
     pub fn record(&mut self, msg: &Message) {
+        if msg.message_type == MessageType::NodeFailure && msg.delivered {
+            self.failures += 1;
+        }
+        if msg.message_type == MessageType::CancelData && msg.delivered {
+            self.cancelled_shares += 1;
+        }
+        if msg.message_type == MessageType::Lookup {
+            self.lookups += msg.started_lookup() as usize;
+            self.lookup_hops += msg.delivered as usize;
+        }
+
         if self.full_export {
             self.sent_messages.push(msg.clone());
             self.total_work += msg.work;
@@ -48,11 +234,205 @@ impl Recording {
         }
     }
 
+    pub fn record_suspicion(&mut self, suspect_alive: bool, suspect_straggler: bool) {
+        self.suspicions += 1;
+        if suspect_alive {
+            self.false_suspicions += 1;
+            if suspect_straggler {
+                self.suspected_stragglers += 1;
+            }
+        }
+    }
+
+    pub fn record_replacement(&mut self, suspect_alive: bool) {
+        self.replacements += 1;
+        if suspect_alive {
+            self.unnecessary_replacements += 1;
+        }
+    }
+
+    pub fn record_election(&mut self, latency: f64) {
+        self.leader_elections += 1;
+        self.election_latency += latency;
+    }
+
+    pub fn record_release(&mut self, time: f64, result: &Share) {
+        self.releases.push(Release {
+            time,
+            contributors: result.contributors.len(),
+            error: (result.share - self.expected_result).abs(),
+        });
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        match &self.result {
+            None if self.result_rejected => Outcome::Rejected,
+            None if self.querier_failed => Outcome::QuerierFailed,
+            None => Outcome::Hung,
+            // Each contribution is made of one share per member of a leaf group
+            Some(result)
+                if result.count
+                    < self.expected_contributions() * self.settings.tree.leaf_group_size() =>
+            {
+                Outcome::Incomplete
+            }
+            Some(_) => Outcome::Complete,
+        }
+    }
+
+    /// Whether the result, without its noise, differs from the sum of the contributors' values
+    pub fn corrupted(&self) -> bool {
+        self.result.as_ref().is_some_and(|result| {
+            (result.share - result.noise - self.expected_result).abs()
+                > 1e-6 * self.expected_result.abs().max(1.0)
+        })
+    }
+
+    /// Differential privacy budget spent by the query, given the noise that reached the result
+    pub fn spent_epsilon(&self) -> Option<f64> {
+        let settings = self.settings.noise.as_ref()?;
+        let result = self.result.as_ref()?;
+        Some(settings.spent_epsilon(result.noises, self.required_noises))
+    }
+
+    /// Distance between the released result and the noiseless ground truth
+    pub fn accuracy_error(&self) -> Option<f64> {
+        self.result
+            .as_ref()
+            .map(|result| (result.share - self.expected_result).abs())
+    }
+
+    /// Whether the querier noticed that the result was tampered with
+    pub fn tampering_detected(&self) -> bool {
+        self.settings.building_blocks.integrity
+            && self.result.as_ref().is_some_and(|result| !result.verify())
+    }
+
+    /// Number of contributors whose value is included in the result
+    pub fn included_contributors(&self) -> usize {
+        self.result
+            .as_ref()
+            .map_or(0, |result| result.contributors.len())
+    }
+
+    /// Contributions the result should include, the targeted contributors unless fewer are
+    /// required
+    pub fn expected_contributions(&self) -> usize {
+        self.settings
+            .required_contributions
+            .map_or(self.targeted_contributors, |required| {
+                required.min(self.targeted_contributors)
+            })
+    }
+
+    /// Contributors recruited beyond the required contributions, relative to them
+    pub fn provisioning_overhead(&self) -> Option<f64> {
+        let required = self.settings.required_contributions?;
+        Some((self.initial_contributors as f64 - required as f64) / required as f64)
+    }
+
+    /// Values shared by every row of the export
+    fn summary(&self) -> Vec<String> {
+        let completeness = (self.included_contributors() as f64
+            / self.expected_contributions() as f64)
+            .to_string();
+        let selectivity =
+            (self.targeted_contributors as f64 / self.initial_contributors as f64).to_string();
+
+        vec![
+            self.settings.seed.clone(),
+            self.settings.building_blocks.failure_handling.to_string(),
+            self.settings.average_failure_time.to_string(),
+            self.settings.health_check_period.to_string(),
+            self.settings.costs.comm.to_string(),
+            self.settings.costs.crypto.to_string(),
+            self.settings.costs.compute.to_string(),
+            self.settings.tree.depth.to_string(),
+            self.settings.tree.fanout.to_string(),
+            self.settings.tree.group_size.to_string(),
+            self.total_work.to_string(),
+            self.total_latency.to_string(),
+            self.total_bandwidth.to_string(),
+            completeness,
+            self.failures.to_string(),
+            self.suspicions.to_string(),
+            self.false_suspicions.to_string(),
+            self.replacements.to_string(),
+            self.unnecessary_replacements.to_string(),
+            self.stragglers.to_string(),
+            self.suspected_stragglers.to_string(),
+            self.omission_faulty.to_string(),
+            self.byzantines.to_string(),
+            self.outcome().to_string(),
+            self.expected_result.to_string(),
+            self.result
+                .as_ref()
+                .map_or(String::new(), |result| result.share.to_string()),
+            self.result
+                .as_ref()
+                .map_or(String::new(), |result| result.count.to_string()),
+            self.corrupted().to_string(),
+            self.tampering_detected().to_string(),
+            self.exposure.as_ref().map_or(String::new(), |exposure| {
+                exposure.exposed_contributors.to_string()
+            }),
+            self.exposure.as_ref().map_or(String::new(), |exposure| {
+                exposure.any_contributor_exposed.to_string()
+            }),
+            self.exposure.as_ref().map_or(String::new(), |exposure| {
+                exposure.exposed_aggregates.to_string()
+            }),
+            self.spent_epsilon()
+                .map_or(String::new(), |epsilon| epsilon.to_string()),
+            self.settings
+                .noise
+                .as_ref()
+                .map_or(String::new(), |noise| noise.delta.to_string()),
+            self.accuracy_error()
+                .map_or(String::new(), |error| error.to_string()),
+            self.round.to_string(),
+            self.refusals.to_string(),
+            self.rejected_aggregates.to_string(),
+            self.consistency_mismatches.to_string(),
+            self.leader_elections.to_string(),
+            self.election_latency.to_string(),
+            self.construction_latency.to_string(),
+            self.construction_work.to_string(),
+            self.excluded_contributors
+                .iter()
+                .map(|address| address.to_string())
+                .join(" "),
+            self.lookups.to_string(),
+            self.lookup_hops.to_string(),
+            self.targeted_contributors.to_string(),
+            selectivity,
+            self.settings
+                .required_contributions
+                .map_or(String::new(), |required| required.to_string()),
+            self.provisioning_overhead()
+                .map_or(String::new(), |overhead| overhead.to_string()),
+            self.cancelled_shares.to_string(),
+            self.expired_deadlines.to_string(),
+            self.missing_children.to_string(),
+            self.partial_contributors.to_string(),
+            self.releases
+                .iter()
+                .map(|release| release.time.to_string())
+                .join(" "),
+            self.releases
+                .iter()
+                .map(|release| release.contributors.to_string())
+                .join(" "),
+            self.releases
+                .iter()
+                .map(|release| release.error.to_string())
+                .join(" "),
+        ]
+    }
+
     pub fn write_to_path(&self, filename: &str) -> Result<(), Box<dyn Error>> {
         let path = format!("outputs/{}", filename);
-        match fs::create_dir("outputs") {
-            _ => (),
-        };
+        fs::create_dir_all("outputs")?;
         let mut writter = match csv::Writer::from_path(path.as_str()) {
             Result::Ok(writter) => writter,
             Result::Err(_) => {
@@ -61,81 +441,45 @@ impl Recording {
             }
         };
 
-        let columns = [
-            "seed",
-            "failure_handling",
-            "average_failure_time",
-            "health_check_period",
-            "communication_cost",
-            "crypto_cost",
-            "compute_cost",
-            "tree_depth",
-            "tree_fanout",
-            "group_size",
-            "total_work",
-            "total_latency",
-            "total_bandwidth",
-            "completeness",
-            "message_type",
-            "emitter_address",
-            "receiver_address",
-            "departure_time",
-            "arrival_time",
-        ];
-
-        let completeness = ((self.initial_contributors - self.final_contributors) as f64
-            / self.initial_contributors as f64)
-            .to_string();
-        writter.write_record(&columns)?;
+        writter.write_record(COLUMNS)?;
         if self.full_export {
             for msg in &self.sent_messages {
-                writter.write_record(&[
-                    self.settings.seed.clone(),
-                    self.settings.building_blocks.failure_handling.to_string(),
-                    self.settings.average_failure_time.to_string(),
-                    self.settings.health_check_period.to_string(),
-                    self.settings.costs.comm.to_string(),
-                    self.settings.costs.crypto.to_string(),
-                    self.settings.costs.compute.to_string(),
-                    self.settings.tree.depth.to_string(),
-                    self.settings.tree.fanout.to_string(),
-                    self.settings.tree.group_size.to_string(),
-                    self.total_work.to_string(),
-                    self.total_latency.to_string(),
-                    self.total_bandwidth.to_string(),
-                    completeness.to_string(),
+                let mut record = self.summary();
+                record.extend([
                     msg.message_type.to_string(),
                     msg.emitter.to_string(),
                     msg.receiver.to_string(),
                     msg.departure_time.to_string(),
                     msg.arrival_time.to_string(),
-                ])?;
+                ]);
+                writter.write_record(&record)?;
             }
         } else {
-            writter.write_record(&[
-                self.settings.seed.clone(),
-                self.settings.building_blocks.failure_handling.to_string(),
-                self.settings.average_failure_time.to_string(),
-                self.settings.health_check_period.to_string(),
-                self.settings.costs.comm.to_string(),
-                self.settings.costs.crypto.to_string(),
-                self.settings.costs.compute.to_string(),
-                self.settings.tree.depth.to_string(),
-                self.settings.tree.fanout.to_string(),
-                self.settings.tree.group_size.to_string(),
-                self.total_work.to_string(),
-                self.total_latency.to_string(),
-                self.total_bandwidth.to_string(),
-                completeness.to_string(),
+            let mut record = self.summary();
+            record.extend([
                 "Stop".to_string(),
                 "0".to_string(),
                 "0".to_string(),
                 "0".to_string(),
                 "0".to_string(),
-            ])?;
+            ]);
+            writter.write_record(&record)?;
         }
         writter.flush()?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_match_columns() {
+        let recording = Recording::new(RunSettings::default(), true);
+
+        // The summary is followed by the 5 fields of a message
+        assert_eq!(recording.summary().len() + 5, COLUMNS.len());
+    }
+}
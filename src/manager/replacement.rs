@@ -0,0 +1,138 @@
+use crate::{
+    common::Address,
+    node::{AggregatorNode, ChannelState, LeafAggregatorNode, Node, NodeRole},
+};
+
+use super::Manager;
+
+impl Manager {
+    /// Puts a recruited node in the position of a suspected aggregator, which leaves the tree
+    pub(super) fn replace_node(&mut self, suspect: Address, time: f64) {
+        let former = self.nodes[&suspect].data();
+        if former.role != NodeRole::Aggregator && former.role != NodeRole::LeafAggregator {
+            // Contributors hold their own data and the querier issued the query
+            return;
+        }
+        let parent = self.nodes[&former.tree_node.parents[0]].data();
+        if !parent
+            .tree_node
+            .children
+            .iter()
+            .flatten()
+            .any(|&child| child == suspect)
+        {
+            // Another member of the group already recruited a replacement
+            return;
+        }
+
+        let address = *self.nodes.keys().max().unwrap() + 1;
+        println!("[@{}] Node #{} replaces node #{}", time, address, suspect);
+        let mut node: Box<dyn Node> = match former.role {
+            NodeRole::Aggregator => AggregatorNode::new(self.settings.clone(), address),
+            _ => LeafAggregatorNode::new(self.settings.clone(), address),
+        };
+        let alive = former.death_time > time;
+        let data = node.data_mut();
+        data.tree_node = former.tree_node.clone();
+        data.tree_node.address = address;
+        data.query = parent.query.clone();
+        data.quota = former.quota;
+        // Recruited nodes are assumed to stay until the end of the query
+        data.death_time = f64::MAX;
+        data.opened_channels = former
+            .opened_channels
+            .iter()
+            .map(|channel| {
+                let peer = channel.peer_address;
+                ChannelState::new(peer, channel.maintained || former.suspects.contains(&peer))
+            })
+            .collect();
+
+        // The neighbours of the position now deal with the replacement
+        let swap = move |peer: &mut Address| {
+            if *peer == suspect {
+                *peer = address;
+            }
+        };
+        data.tree_node.members.iter_mut().for_each(swap);
+        for (_, neighbour) in self.nodes.iter_mut().filter(|(&peer, _)| peer != suspect) {
+            let data = neighbour.data_mut();
+            data.tree_node.members.iter_mut().for_each(swap);
+            data.tree_node.parents.iter_mut().for_each(swap);
+            data.tree_node.children.iter_mut().flatten().for_each(swap);
+            swap(&mut data.leader);
+            let suspected = data.suspects.contains(&suspect);
+            for channel in data.opened_channels.iter_mut() {
+                if channel.peer_address == suspect {
+                    channel.peer_address = address;
+                    channel.maintained |= suspected;
+                }
+            }
+            data.suspects.retain(|&peer| peer != suspect);
+            data.awaited_health_checks.retain(|&peer| peer != suspect);
+        }
+        let member = node
+            .data()
+            .tree_node
+            .members
+            .iter()
+            .find(|&&member| member != address)
+            .copied();
+        node.data_mut().leader = member.map_or(address, |member| self.nodes[&member].data().leader);
+
+        if alive {
+            // The falsely suspected node is dismissed from the tree
+            self.nodes.get_mut(&suspect).unwrap().data_mut().death_time = time;
+            self.cancel_timers(suspect);
+        }
+        self.recording.record_replacement(alive);
+
+        let messages = node.join(time);
+        self.nodes.insert(address, node);
+        for msg in messages {
+            self.insert_message(msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        manager::{Manager, Outcome},
+        run::{BuildingBlocks, DiscoveryMode},
+    };
+
+    #[test]
+    fn replace_failed_aggregator() {
+        let mut manager = Manager::default();
+        manager.settings.building_blocks = BuildingBlocks::resilient();
+        manager.setup();
+
+        // Node #1 is a top-level aggregator, it dies before its children answer
+        manager.kill_node(1, manager.current_time + 1.0);
+        manager.run();
+
+        let recording = &manager.recording;
+        assert_eq!(recording.replacements, 1);
+        assert_eq!(recording.unnecessary_replacements, 0);
+        assert_eq!(recording.outcome(), Outcome::Complete);
+    }
+
+    #[test]
+    fn recruit_through_overlay() {
+        let mut manager = Manager::default();
+        manager.settings.building_blocks = BuildingBlocks::resilient();
+        manager.settings.discovery = DiscoveryMode::Chord;
+        manager.setup();
+
+        manager.kill_node(1, manager.current_time + 1.0);
+        manager.run();
+
+        // The replacement is found by a lookup, which may take several hops
+        let recording = &manager.recording;
+        assert!(recording.lookups >= 1);
+        assert!(recording.lookup_hops >= 1);
+        assert_eq!(recording.replacements, 1);
+        assert_eq!(recording.outcome(), Outcome::Complete);
+    }
+}
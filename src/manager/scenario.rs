@@ -0,0 +1,234 @@
+use crate::{
+    common::Address,
+    scenario::{Scenario, ScenarioAction, Selector},
+};
+
+use super::Manager;
+
+impl Manager {
+    /// Returns the groups whose nodes are at the given depth of the tree, from left to right
+    pub fn groups_at_depth(&self, depth: u8) -> Vec<Vec<Address>> {
+        let top = self.settings.tree.depth;
+        if depth > top {
+            return vec![];
+        }
+        let mut groups = self
+            .nodes
+            .get(&self.querier_address)
+            .unwrap()
+            .data()
+            .tree_node
+            .children
+            .clone();
+
+        for _ in depth..top {
+            groups = groups
+                .iter()
+                .flat_map(|group| {
+                    self.nodes
+                        .get(group.first().unwrap())
+                        .unwrap()
+                        .data()
+                        .tree_node
+                        .children
+                        .clone()
+                })
+                .collect();
+        }
+
+        groups
+    }
+
+    /// Finds the addresses of the nodes matching a selector
+    pub fn select(&self, selector: &Selector) -> Vec<Address> {
+        let group = match self.groups_at_depth(selector.depth).get(selector.group) {
+            Some(group) => group.clone(),
+            None => return vec![],
+        };
+
+        let mut selected = match selector.member {
+            Some(member) => group.get(member).into_iter().copied().collect(),
+            None => group.clone(),
+        };
+
+        if selector.subtree {
+            let mut descendants = self
+                .nodes
+                .get(group.first().unwrap())
+                .unwrap()
+                .data()
+                .tree_node
+                .children
+                .clone();
+            while let Some(child_group) = descendants.pop() {
+                descendants.extend(
+                    self.nodes
+                        .get(child_group.first().unwrap())
+                        .unwrap()
+                        .data()
+                        .tree_node
+                        .children
+                        .clone(),
+                );
+                selected.extend(child_group);
+            }
+        }
+
+        selected.sort();
+        selected.dedup();
+        selected
+    }
+
+    /// Schedules the actions of a scenario as events, must be called after the setup
+    pub fn schedule_scenario(&mut self, scenario: &Scenario) {
+        for action in &scenario.actions {
+            let target = match action {
+                ScenarioAction::Kill { target, .. } => target,
+                ScenarioAction::Delay { target, .. } => target,
+                ScenarioAction::SlowDown { target, .. } => target,
+                ScenarioAction::LinkLatency { target, .. } => target,
+                ScenarioAction::Partition { target, .. } => target,
+            };
+            let addresses = self.select(target);
+            if addresses.is_empty() {
+                println!("Scenario selector {:?} matched no node", target);
+            }
+
+            match action {
+                ScenarioAction::LinkLatency {
+                    at, latency, peers, ..
+                } => {
+                    for peer in self.select(peers) {
+                        for &address in &addresses {
+                            self.set_link_latency(address, peer, *latency, *at);
+                        }
+                    }
+                }
+                ScenarioAction::Partition {
+                    from,
+                    until,
+                    mode,
+                    peers,
+                    ..
+                } => {
+                    let peers = self.select(peers);
+                    self.partition(&addresses, &peers, *from, *until, *mode);
+                }
+                _ => (),
+            }
+
+            for address in addresses {
+                match action {
+                    ScenarioAction::Kill { at, .. } => self.kill_node(address, *at),
+                    ScenarioAction::Delay {
+                        from, until, delay, ..
+                    } => {
+                        self.delay_node(address, *delay, *from);
+                        if let Some(until) = until {
+                            // Other delays of the node are left as they are
+                            self.delay_node(address, -*delay, *until);
+                        }
+                    }
+                    ScenarioAction::SlowDown { at, factor, .. } => {
+                        self.slow_down(address, *factor, *at)
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::MessageType;
+
+    use super::*;
+
+    #[test]
+    fn schedule_scenario() {
+        let mut manager = Manager::default();
+        manager.setup();
+        manager.message_queue.clear();
+
+        let leader = manager.groups_at_depth(2)[1][0];
+        let subtree = manager.select(&Selector {
+            depth: 2,
+            group: 1,
+            member: None,
+            subtree: true,
+        });
+        manager.schedule_scenario(
+            &Scenario::parse(
+                r#"{
+                    "actions": [
+                        { "type": "kill", "at": 1500, "target": { "depth": 2, "group": 1, "member": 0 } },
+                        { "type": "delay", "from": 1600, "until": 2600, "delay": 500, "target": { "depth": 2, "group": 1, "subtree": true } }
+                    ]
+                }"#,
+            )
+            .unwrap(),
+        );
+
+        let failures = manager
+            .message_queue
+            .iter()
+            .filter(|msg| msg.message_type == MessageType::NodeFailure)
+            .collect::<Vec<_>>();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].receiver, leader);
+        assert_eq!(failures[0].arrival_time, 1500.0);
+
+        // The group, its leaf aggregator children and their contributors are delayed then restored
+        assert!(subtree.len() > 3 + 4 * 3);
+        assert_eq!(
+            manager
+                .message_queue
+                .iter()
+                .filter(|msg| msg.message_type == MessageType::SetLinkDelay)
+                .count(),
+            2 * subtree.len()
+        );
+    }
+
+    #[test]
+    fn select_by_tree_depth() {
+        let mut manager = Manager::default();
+        manager.setup();
+
+        for depth in 0..=manager.settings.tree.depth {
+            let group = &manager.groups_at_depth(depth)[0];
+            assert!(group
+                .iter()
+                .all(|member| manager.nodes[member].data().tree_node.depth == depth));
+        }
+        assert_eq!(manager.groups_at_depth(3), vec![vec![1, 2, 3]]);
+        assert!(manager.groups_at_depth(4).is_empty());
+    }
+
+    #[test]
+    fn overlapping_delays() {
+        let mut manager = Manager::default();
+        manager.setup();
+        manager.message_queue.clear();
+
+        manager.schedule_scenario(
+            &Scenario::parse(
+                r#"{
+                    "actions": [
+                        { "type": "delay", "from": 2000, "until": 4000, "delay": 500, "target": { "depth": 3, "group": 0, "member": 0 } },
+                        { "type": "delay", "from": 3000, "until": 5000, "delay": 200, "target": { "depth": 3, "group": 0, "member": 0 } }
+                    ]
+                }"#,
+            )
+            .unwrap(),
+        );
+
+        // Each delay is lifted on its own
+        let mut delays = vec![];
+        while manager.handle_next_message() {
+            delays.push(manager.nodes[&1].data().link_delay);
+        }
+        assert_eq!(delays, vec![500.0, 700.0, 200.0, 0.0]);
+    }
+}
@@ -1,10 +1,20 @@
-use std::cmp::Ordering;
+use std::collections::HashMap;
 
 use itertools::Itertools;
-use rand::Rng;
-use rand_distr::{Distribution, Exp};
-
-use crate::{common::*, node::*, run::FailureHandlingMode};
+use rand::{seq::SliceRandom, Rng};
+use rand_distr::{Distribution, Exp, Gamma, LogNormal, Normal, Poisson};
+
+use crate::{
+    common::*,
+    message::{Attributes, Message, MessageType},
+    node::*,
+    overlay::Chord,
+    population::Population,
+    run::{
+        ConstructionMode, ContributorsDistribution, DiscoveryMode, NoiseLocation, NoiseMechanism,
+        SlowdownDistribution,
+    },
+};
 
 use super::Manager;
 
@@ -13,21 +23,203 @@ impl Manager {
     pub(super) fn generate_failures(&mut self) {
         let exp = Exp::new(1.0 / self.settings.average_failure_time).unwrap();
 
-        for (_, node) in &mut self.nodes {
+        for node in self.nodes.values_mut() {
             // Nodes don't fail when the average failure time is 0
-            if (self.settings.average_failure_time == 0.0
-                || self.settings.average_failure_time == f64::MAX)
+            if self.settings.average_failure_time == 0.0
+                || self.settings.average_failure_time == f64::MAX
             {
                 node.data_mut().death_time = f64::MAX;
             } else {
-                node.data_mut().death_time = exp.sample(&mut self.rng);
+                let reliability = node
+                    .data()
+                    .device
+                    .as_ref()
+                    .map_or(1.0, |device| device.reliability);
+                node.data_mut().death_time = exp.sample(&mut self.rng) * reliability;
+            }
+        }
+    }
+
+    /// Samples a distinct device of the population for each node below the querier
+    pub(super) fn assign_devices(&mut self) {
+        let settings = match &self.settings.population {
+            Some(settings) => settings.clone(),
+            None => return,
+        };
+        let population = self
+            .population
+            .get_or_insert_with(|| Population::generate(&settings, &mut self.rng))
+            .clone();
+
+        let mut devices = population.devices.iter().collect::<Vec<_>>();
+        devices.shuffle(&mut self.rng);
+
+        // Aggregators are drawn first among the allowed classes
+        let addresses = self
+            .nodes
+            .keys()
+            .copied()
+            .filter(|&address| address != self.querier_address)
+            .sorted_by_key(|address| {
+                (
+                    self.nodes[address].data().role == NodeRole::Contributor,
+                    *address,
+                )
+            })
+            .collect::<Vec<_>>();
+        for address in addresses {
+            let node = self.nodes.get_mut(&address).unwrap();
+            let device = if node.data().role == NodeRole::Contributor {
+                // Taken from the end, which the aggregators did not sift through
+                devices.pop()
+            } else {
+                devices
+                    .iter()
+                    .position(|device| {
+                        settings.aggregator_classes.is_empty()
+                            || settings.aggregator_classes.contains(&device.class)
+                    })
+                    .map(|index| devices.remove(index))
             }
+            .expect("The population is too small for the tree")
+            .clone();
+
+            node.data_mut().slowdown = device.slowdown;
+            node.data_mut().device = Some(device);
+        }
+    }
+
+    /// Sets the slowdown factor of each node in the simulation.
+    pub(super) fn generate_stragglers(&mut self) {
+        let settings = self.settings.stragglers.clone();
+
+        // Sorted to draw the same stragglers for a given seed
+        for address in self.nodes.keys().copied().sorted().collect::<Vec<_>>() {
+            let node = self.nodes.get_mut(&address).unwrap();
+            if !settings.roles.is_empty() && !settings.roles.contains(&node.data().role) {
+                continue;
+            }
+            if settings.fraction == 0.0 || !self.rng.gen_bool(settings.fraction) {
+                continue;
+            }
+
+            node.data_mut().slowdown = match settings.slowdown {
+                SlowdownDistribution::Fixed(factor) => factor,
+                SlowdownDistribution::Uniform(low, high) => self.rng.gen_range(low..=high),
+                SlowdownDistribution::LogNormal { mu, sigma } => {
+                    LogNormal::new(mu, sigma).unwrap().sample(&mut self.rng)
+                }
+            };
+            self.recording.stragglers += 1;
+        }
+    }
+
+    /// Draws each aggregator with the given probability
+    fn draw_aggregators(&mut self, fraction: f64) -> Vec<Address> {
+        let mut drawn = vec![];
+        for address in self.nodes.keys().copied().sorted().collect::<Vec<_>>() {
+            let role = self.nodes.get(&address).unwrap().data().role;
+            if role != NodeRole::Aggregator && role != NodeRole::LeafAggregator {
+                continue;
+            }
+            if fraction > 0.0 && self.rng.gen_bool(fraction) {
+                drawn.push(address);
+            }
+        }
+
+        drawn
+    }
+
+    /// Makes some aggregators omission-faulty, they stay alive but misbehave
+    pub(super) fn generate_omissions(&mut self) {
+        let settings = self.settings.omissions.clone();
+
+        for address in self.draw_aggregators(settings.fraction) {
+            self.nodes.get_mut(&address).unwrap().data_mut().omission = Some(settings.behavior);
+            self.recording.omission_faulty += 1;
+        }
+    }
+
+    /// Makes some aggregators byzantine, they tamper with the aggregates they forward
+    pub(super) fn generate_byzantines(&mut self) {
+        let settings = self.settings.byzantines.clone();
+
+        for address in self.draw_aggregators(settings.fraction) {
+            self.nodes.get_mut(&address).unwrap().data_mut().byzantine = Some(settings.behavior);
+            self.recording.byzantines += 1;
+        }
+    }
+
+    /// Draws the differential privacy noise added by each noise generator
+    pub(super) fn generate_noise(&mut self) {
+        let settings = match self.settings.noise.clone() {
+            Some(settings) => settings,
+            None => return,
+        };
+
+        let generators = self
+            .nodes
+            .keys()
+            .copied()
+            .sorted()
+            .filter(|address| {
+                let data = self.nodes.get(address).unwrap().data();
+                match settings.location {
+                    NoiseLocation::Contributors => data.role == NodeRole::Contributor,
+                    // Only the leader of the group adds noise to its share of the aggregate
+                    NoiseLocation::LeafAggregators => {
+                        data.role == NodeRole::LeafAggregator
+                            && data.tree_node.members.first() == Some(address)
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // The noise is split so that the required contributions sum up to the full noise
+        let required =
+            ((generators.len() as f64 * (1.0 - settings.dropout_tolerance)).ceil() as usize).max(1);
+        for address in generators {
+            let noise = match settings.mechanism {
+                NoiseMechanism::Laplace => {
+                    let gamma = Gamma::new(1.0 / required as f64, settings.scale()).unwrap();
+                    gamma.sample(&mut self.rng) - gamma.sample(&mut self.rng)
+                }
+                NoiseMechanism::Gaussian => {
+                    Normal::new(0.0, settings.scale() / (required as f64).sqrt())
+                        .unwrap()
+                        .sample(&mut self.rng)
+                }
+            };
+            self.nodes.get_mut(&address).unwrap().data_mut().noise = Some(noise);
+        }
+        self.recording.required_noises = required;
+    }
+
+    /// Schedules a failure event at the time of death of each node
+    pub(super) fn schedule_failures(&mut self) {
+        let failures = self
+            .nodes
+            .values()
+            .filter(|node| node.data().death_time < f64::MAX)
+            .map(|node| {
+                Message::new_timeout(
+                    MessageType::NodeFailure,
+                    node.data().address,
+                    0.0,
+                    node.data().death_time,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        for msg in failures {
+            self.insert_message(msg);
         }
     }
 
     /// Initializes the channels between nodes and send initial messages
     pub(super) fn initialize_nodes(&mut self) {
         let mut messages = vec![];
+        let querier = self.querier_address;
         for (_, node) in self.nodes.iter_mut() {
             let position = node
                 .data()
@@ -37,8 +229,6 @@ impl Manager {
                 .position(|x| x == &node.data().address)
                 .unwrap();
 
-            messages.append(&mut node.setup(self.current_time));
-
             if node.data().role == NodeRole::Querier {
                 // Channels with children
                 for child in node.data().tree_node.children[0].clone() {
@@ -47,14 +237,18 @@ impl Manager {
                         .push(ChannelState::new(child, true));
                 }
             } else if node.data().role == NodeRole::Aggregator {
-                // Channels with parent
-                let parent_address = node.data().tree_node.parents[position];
+                // Channels with parent, the top-level group monitors the querier
+                let parents = &node.data().tree_node.parents;
+                let parent_address = parents[position % parents.len()];
                 node.data_mut()
                     .opened_channels
-                    .push(ChannelState::new(parent_address, false));
+                    .push(ChannelState::new(parent_address, parent_address == querier));
 
-                // Leader opens with members
-                if position == 0 {
+                // Leader opens with members, which monitor it to elect a new one when it fails.
+                // The leader only monitors them when they have to agree
+                let leader = node.data().leader;
+                let consistency = node.data().settings.building_blocks.consistency;
+                if leader == node.data().address {
                     let members = node
                         .data()
                         .tree_node
@@ -67,27 +261,34 @@ impl Manager {
                     for member in members {
                         node.data_mut()
                             .opened_channels
-                            .push(ChannelState::new(member, false));
+                            .push(ChannelState::new(member, consistency));
                     }
                 } else {
-                    let leader = node.data().tree_node.members[0];
                     node.data_mut()
                         .opened_channels
-                        .push(ChannelState::new(leader, false));
+                        .push(ChannelState::new(leader, true));
                 }
 
                 // Channels with children
+                let group_size = node.data().tree_node.members.len();
                 for group in node.data().tree_node.children.clone() {
-                    node.data_mut()
-                        .opened_channels
-                        .push(ChannelState::new(group[position], true));
+                    for (_, &child) in group
+                        .iter()
+                        .enumerate()
+                        .filter(|(child_position, _)| child_position % group_size == position)
+                    {
+                        node.data_mut()
+                            .opened_channels
+                            .push(ChannelState::new(child, true));
+                    }
                 }
             } else if node.data().role == NodeRole::LeafAggregator {
-                // Channels with parent
-                let parent_address = node.data().tree_node.parents[position];
+                // Channels with parent, the top-level group monitors the querier
+                let parents = &node.data().tree_node.parents;
+                let parent_address = parents[position % parents.len()];
                 node.data_mut()
                     .opened_channels
-                    .push(ChannelState::new(parent_address, false));
+                    .push(ChannelState::new(parent_address, parent_address == querier));
 
                 // Leader opens with members
                 let members = node
@@ -105,79 +306,152 @@ impl Manager {
                         .push(ChannelState::new(member, true));
                 }
             }
+
+            if self.settings.construction == ConstructionMode::Simulated {
+                // Channels are opened through messages, the querier starts the construction
+                let channels = std::mem::take(&mut node.data_mut().opened_channels);
+                node.data_mut().pending_channels = channels;
+                if node.data().role == NodeRole::Querier {
+                    messages.append(&mut node.open_channels());
+                }
+            } else {
+                messages.append(&mut node.setup(self.current_time));
+            }
         }
 
+        self.pending_handshakes = messages
+            .iter()
+            .filter(|msg| msg.message_type == MessageType::OpenChannel || msg.started_lookup())
+            .count();
         for msg in messages {
             self.insert_message(msg);
         }
     }
 
-    /// Initialize the tree to failures that occured during the tree creation
-    pub(super) fn initialize_tree_failures(&mut self) {
-        if self.settings.building_blocks.failure_handling == FailureHandlingMode::NodeReplacement {
-            // When replacing nodes, extend the construction by the time it takes to replace a node times the number of failures
-            let replacement_time: f64 =
-                10.0 * self.settings.costs.crypto + 8.0 * self.settings.costs.comm;
+    /// Draws the attributes of the contributors targeted by queries
+    pub(super) fn generate_attributes(&mut self) {
+        let targeting = match self.settings.targeting.clone() {
+            Some(targeting) => targeting,
+            None => return,
+        };
 
-            let nodes_sorted = self
-                .nodes
-                .iter()
-                .map(|(_, x)| x)
-                .sorted_by(|a, b| {
-                    if a.data().death_time > b.data().death_time {
-                        Ordering::Greater
-                    } else if a.data().death_time == b.data().death_time {
-                        Ordering::Equal
-                    } else {
-                        Ordering::Less
-                    }
-                })
-                .collect::<Vec<_>>();
+        let contributors = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.data().role == NodeRole::Contributor)
+            .map(|(address, _)| *address)
+            .sorted()
+            .collect::<Vec<_>>();
+        for address in contributors {
+            let mut attributes = Attributes::new();
+            if let Some(device) = &self.nodes[&address].data().device {
+                attributes.insert("region".to_string(), device.region.to_string());
+            }
+            for attribute in &targeting.attributes {
+                let value = attribute.values.choose(&mut self.rng).unwrap();
+                attributes.insert(attribute.name.clone(), value.clone());
+            }
+            self.nodes.get_mut(&address).unwrap().data_mut().attributes = attributes;
+        }
+    }
 
-            let mut i = 0;
-            while nodes_sorted.get(i).is_some()
-                && self.current_time > nodes_sorted.get(i).unwrap().data().death_time
-            {
-                self.current_time += replacement_time;
-                i += 1;
+    /// Splits the required contributions among the leaf groups, in proportion to their contributors,
+    /// and sums them up the tree
+    pub(super) fn initialize_quotas(&mut self) {
+        let required = match self.settings.required_contributions {
+            Some(required) => required,
+            None => return,
+        };
+
+        let leaves = self
+            .nodes
+            .values()
+            .filter(|node| node.data().role == NodeRole::LeafAggregator)
+            .map(|node| &node.data().tree_node)
+            .filter(|tree_node| tree_node.members[0] == tree_node.address)
+            .map(|tree_node| (tree_node.members.clone(), tree_node.children.len()))
+            .sorted()
+            .collect::<Vec<_>>();
+        let recruited = leaves
+            .iter()
+            .map(|(_, contributors)| contributors)
+            .sum::<usize>();
+        let required = required.min(recruited);
+
+        // Rounding the cumulated shares keeps the total at the required contributions
+        let mut quotas = HashMap::new();
+        let mut cumulated = 0;
+        for (members, contributors) in leaves {
+            let before = (required * cumulated + recruited / 2) / recruited;
+            cumulated += contributors;
+            let after = (required * cumulated + recruited / 2) / recruited;
+            for member in members {
+                quotas.insert(member, after - before);
             }
-        } else if self.settings.building_blocks.failure_handling
-            == FailureHandlingMode::LocalFailurePropagation
-        {
-            // Failed nodes are dropped by their parents
-            let failed_nodes: Vec<_> = self
-                .nodes
+        }
+
+        // Aggregators wait for the quotas of the children they receive from, so that the
+        // contributions beyond the quota of a leaf make up for the dropouts of another one
+        let aggregators = self
+            .nodes
+            .values()
+            .filter(|node| node.data().role == NodeRole::Aggregator)
+            .map(|node| &node.data().tree_node)
+            .sorted_by_key(|tree_node| (tree_node.depth, tree_node.address))
+            .cloned()
+            .collect::<Vec<_>>();
+        for tree_node in aggregators {
+            let group_size = tree_node.members.len();
+            let position = tree_node
+                .members
                 .iter()
-                .filter(|(_, x)| x.data().death_time < self.current_time)
-                .map(|(_, x)| x.data().address)
-                .collect();
+                .position(|&member| member == tree_node.address)
+                .unwrap();
+            let quota = tree_node
+                .children
+                .iter()
+                .flat_map(|child_group| {
+                    child_group
+                        .iter()
+                        .enumerate()
+                        .filter(|(child_position, _)| child_position % group_size == position)
+                        .map(|(_, child)| quotas[child])
+                })
+                .sum();
+            quotas.insert(tree_node.address, quota);
+        }
 
-            // Stop subtrees recursively
-            // Note: stopping too many nodes, tree creation should continue despite failures when not on the leader
-            let mut stopped_nodes: usize = 0;
-            fn stop_child(manager: &mut Manager, stopped_nodes: &mut usize, target: &Address) {
-                let node = manager.nodes.get(target).unwrap().data().tree_node.clone();
-                *stopped_nodes += node.children.len();
+        for (address, quota) in quotas {
+            self.nodes.get_mut(&address).unwrap().data_mut().quota = Some(quota);
+        }
+    }
 
-                for member in &node.members {
-                    manager.nodes.get_mut(member).unwrap().data_mut().death_time = 0.0;
-                }
-                for child in node.children {
-                    stop_child(manager, stopped_nodes, child.first().unwrap());
-                }
-            }
-            for node in &failed_nodes {
-                stop_child(self, &mut stopped_nodes, node);
-            }
-        } else if self.settings.building_blocks.failure_handling
-            == FailureHandlingMode::FullFailurePropagation
-        {
-            // self.current_time = self
-            //     .nodes
-            //     .iter()
-            //     .map(|(_, x)| x.data().death_time)
-            //     .reduce(|a, b| if a > b { b } else { a })
-            //     .unwrap();
+    /// Joins all the nodes in the overlay and gives them their finger tables
+    pub(super) fn build_overlay(&mut self) {
+        if self.settings.discovery != DiscoveryMode::Chord {
+            return;
+        }
+
+        let overlay = Chord::new(self.nodes.keys().copied());
+        for (address, node) in self.nodes.iter_mut() {
+            node.data_mut().fingers = overlay.fingers(*address).to_vec();
+        }
+        self.overlay = Some(overlay);
+    }
+
+    /// Ends the construction of the tree and starts the aggregation
+    pub(super) fn finish_construction(&mut self) {
+        println!("[@{}] The tree is built", self.current_time);
+        self.recording.construction_latency = self.current_time;
+        self.recording.construction_work = self.recording.total_work;
+
+        let mut messages = vec![];
+        for address in self.nodes.keys().copied().sorted().collect::<Vec<_>>() {
+            let node = self.nodes.get_mut(&address).unwrap();
+            messages.append(&mut node.setup(self.current_time));
+        }
+        for msg in messages {
+            self.insert_message(msg);
         }
     }
 
@@ -189,10 +463,12 @@ impl Manager {
         max_depth: u8,
         current_depth: u8,
         starting_address: Address,
+        contributors: &mut impl Iterator<Item = usize>,
     ) -> Address {
+        let level = (max_depth - current_depth) as usize;
         let group_nodes = if current_depth > 0 {
             // Aggregator group
-            (0..manager.settings.tree.group_size)
+            (0..manager.settings.tree.group_size_at(level))
                 .map(|i| starting_address.increment(Some(i as usize)))
                 .collect()
         } else {
@@ -213,7 +489,7 @@ impl Manager {
         {
             manager
                 .nodes
-                .get_mut(&member)
+                .get_mut(member)
                 .unwrap()
                 .data_mut()
                 .tree_node
@@ -234,6 +510,7 @@ impl Manager {
 
             node.data_mut().tree_node.depth = current_depth;
             node.data_mut().tree_node.members = group_nodes.clone();
+            node.data_mut().leader = group_nodes[0];
             node.data_mut().tree_node.parents = manager
                 .nodes
                 .get(&parent_group_first_node)
@@ -246,44 +523,69 @@ impl Manager {
         }
 
         if current_depth > 1 {
-            for _ in 0..manager.settings.tree.fanout {
+            for _ in 0..manager.settings.tree.fanout_at(level) {
                 next_node = Manager::create_tree_node(
                     manager,
                     starting_address,
                     max_depth,
                     current_depth - 1,
                     next_node,
+                    contributors,
                 );
             }
         } else if current_depth == 1 {
             // The node is a leaf aggregator
-            unsafe {
-                let number_of_contributors = manager
-                    .rng
-                    .gen_range(
-                        (manager.settings.tree.fanout as f64)
-                            ..(manager.settings.tree.fanout * manager.settings.tree.fanout) as f64,
-                    )
-                    .to_int_unchecked();
-
-                for _ in 0..number_of_contributors {
-                    next_node = Manager::create_tree_node(
-                        manager,
-                        starting_address,
-                        max_depth,
-                        0,
-                        next_node,
-                    );
-                }
+            for _ in 0..contributors.next().unwrap() {
+                next_node = Manager::create_tree_node(
+                    manager,
+                    starting_address,
+                    max_depth,
+                    0,
+                    next_node,
+                    contributors,
+                );
             }
         }
 
         next_node
     }
+
+    /// Draws the number of contributors below each leaf aggregator group, in creation order
+    pub(super) fn contributors_per_leaf(&mut self) -> Vec<usize> {
+        let tree = self.settings.tree.clone();
+        let leaves = tree.leaf_groups();
+
+        if let Some(target) = tree.target_contributors {
+            return (0..leaves)
+                .map(|leaf| target / leaves + (leaf < target % leaves) as usize)
+                .collect();
+        }
+
+        (0..leaves)
+            .map(|_| match tree.contributors {
+                ContributorsDistribution::FanoutRange => unsafe {
+                    self.rng
+                        .gen_range((tree.fanout as f64)..(tree.fanout * tree.fanout) as f64)
+                        .to_int_unchecked()
+                },
+                ContributorsDistribution::Fixed(contributors) => contributors,
+                ContributorsDistribution::Uniform(low, high) => self.rng.gen_range(low..=high),
+                ContributorsDistribution::Poisson(lambda) => {
+                    Poisson::new(lambda).unwrap().sample(&mut self.rng) as usize
+                }
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::{
+        manager::Outcome,
+        population::{DeviceClass, DeviceProfile, PopulationSettings},
+        run::{BuildingBlocks, OmissionBehavior, OmissionSettings, StragglerSettings},
+    };
+
     use super::*;
 
     #[test]
@@ -301,4 +603,359 @@ mod tests {
 
         manager.generate_failures();
     }
+
+    #[test]
+    fn tree_shapes() {
+        let mut manager = Manager::default();
+        manager.settings.tree.fanouts = vec![2, 3];
+        manager.settings.tree.group_sizes = vec![3, 4, 5];
+        manager.settings.tree.contributors = ContributorsDistribution::Fixed(2);
+
+        manager.setup();
+        manager.run();
+
+        // Querier, 1 root group of 3, 2 groups of 4, 6 leaf groups of 5 and 12 contributors
+        assert_eq!(manager.nodes.len(), 1 + 3 + 2 * 4 + 6 * 5 + 12);
+        assert_eq!(manager.recording.outcome(), Outcome::Complete);
+        assert_eq!(
+            manager.recording.result.as_ref().unwrap().share,
+            manager.recording.expected_result
+        );
+
+        let mut manager = Manager::default();
+        manager.settings.tree.target_contributors = Some(50);
+        manager.setup();
+        assert_eq!(manager.recording.initial_contributors, 50);
+
+        // Both bounds of the range can be drawn
+        let manager = Manager::configured(|settings| {
+            settings.tree.contributors = ContributorsDistribution::Uniform(3, 3)
+        });
+        assert_eq!(manager.recording.initial_contributors, 16 * 3);
+    }
+
+    #[test]
+    fn leaves_without_contributors() {
+        let mut manager = Manager::configured(|settings| {
+            settings.tree.contributors = ContributorsDistribution::Fixed(0)
+        });
+        manager.run();
+        assert_eq!(manager.recording.outcome(), Outcome::Complete);
+    }
+
+    #[test]
+    fn poisson_contributors() {
+        let mut manager = Manager::configured(|settings| {
+            settings.tree.contributors = ContributorsDistribution::Poisson(1.0)
+        });
+        // Some leaf groups are left without contributors
+        assert!(manager.nodes.values().any(|node| {
+            node.data().role == NodeRole::LeafAggregator
+                && node.data().tree_node.children.is_empty()
+        }));
+        manager.run();
+        assert_eq!(manager.recording.outcome(), Outcome::Complete);
+    }
+
+    #[test]
+    fn target_contributors() {
+        // A single contributor for each leaf group
+        let mut manager =
+            Manager::configured(|settings| settings.tree.target_contributors = Some(16));
+        manager.run();
+        assert_eq!(manager.recording.outcome(), Outcome::Complete);
+        assert_eq!(manager.recording.included_contributors(), 16);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be spread")]
+    fn too_few_target_contributors() {
+        Manager::configured(|settings| settings.tree.target_contributors = Some(10));
+    }
+
+    #[test]
+    #[should_panic(expected = "empty range")]
+    fn empty_contributors_range() {
+        let mut manager = Manager::default();
+        manager.settings.tree.contributors = ContributorsDistribution::Uniform(4, 3);
+        manager.setup();
+    }
+
+    #[test]
+    fn devices_from_population() {
+        let settings = PopulationSettings {
+            size: 1000,
+            profiles: vec![
+                DeviceProfile {
+                    class: DeviceClass::Phone,
+                    fraction: 0.7,
+                    slowdown: 1.0,
+                    reliability: 1.0,
+                },
+                DeviceProfile {
+                    class: DeviceClass::Server,
+                    fraction: 0.3,
+                    slowdown: 1.0,
+                    reliability: 1.0,
+                },
+            ],
+            regions: 1,
+            aggregator_classes: vec![DeviceClass::Server],
+        };
+
+        let mut roles = vec![];
+        let mut population = None;
+        for seed in ["a", "b"] {
+            let mut manager = Manager::new(
+                BuildingBlocks::default(),
+                seed.to_string(),
+                Manager::default().settings.tree,
+            );
+            manager.settings.population = Some(settings.clone());
+            manager.population = population;
+            manager.setup();
+
+            let devices = manager
+                .nodes
+                .values()
+                .filter_map(|node| node.data().device.as_ref())
+                .collect::<Vec<_>>();
+            assert_eq!(devices.len(), manager.nodes.len() - 1);
+            assert_eq!(
+                devices.iter().map(|device| device.id).unique().count(),
+                devices.len()
+            );
+            for node in manager.nodes.values() {
+                if let Some(device) = &node.data().device {
+                    if node.data().role != NodeRole::Contributor {
+                        assert_eq!(device.class, DeviceClass::Server);
+                    }
+                    roles.push((device.id, node.data().role));
+                }
+            }
+            population = manager.population;
+        }
+
+        // Some server aggregates in one query and contributes in the other
+        assert!(roles.iter().any(|&(id, role)| role == NodeRole::Contributor
+            && roles
+                .iter()
+                .any(|&(other, other_role)| other == id && other_role != NodeRole::Contributor)));
+    }
+
+    #[test]
+    fn simulated_construction() {
+        let mut estimated = Manager::default();
+        estimated.setup();
+
+        let mut manager = Manager::default();
+        manager.settings.construction = ConstructionMode::Simulated;
+        manager.setup();
+        manager.run();
+
+        assert_eq!(manager.recording.outcome(), Outcome::Complete);
+        assert!(manager.recording.construction_latency > 0.0);
+        assert!(manager.recording.construction_work > 0.0);
+        // The same channels are opened as when the construction is estimated
+        for (address, node) in &manager.nodes {
+            assert!(node.data().pending_channels.is_empty());
+            assert_eq!(
+                node.data().opened_channels.len(),
+                estimated.nodes[address].data().opened_channels.len()
+            );
+        }
+    }
+
+    #[test]
+    fn overlay_discovery() {
+        let mut manager = Manager::default();
+        manager.settings.construction = ConstructionMode::Simulated;
+        manager.settings.discovery = DiscoveryMode::Chord;
+        manager.setup();
+        manager.run();
+
+        assert_eq!(manager.recording.outcome(), Outcome::Complete);
+        // Each channel opening follows a lookup of the peer, which may take several hops
+        let openings = manager
+            .recording
+            .sent_messages
+            .iter()
+            .filter(|msg| msg.message_type == MessageType::OpenChannel)
+            .count();
+        assert_eq!(manager.recording.lookups, openings);
+        assert!(manager.recording.lookup_hops > manager.recording.lookups);
+
+        let mut direct = Manager::default();
+        direct.settings.construction = ConstructionMode::Simulated;
+        direct.setup();
+        direct.run();
+        assert_eq!(direct.recording.lookups, 0);
+        assert!(manager.recording.construction_latency > direct.recording.construction_latency);
+    }
+
+    #[test]
+    fn stragglers_by_role() {
+        let mut manager = Manager::default();
+        manager.settings.stragglers = StragglerSettings {
+            fraction: 1.0,
+            slowdown: SlowdownDistribution::Fixed(3.0),
+            roles: vec![NodeRole::Contributor],
+        };
+
+        manager.setup();
+
+        for node in manager.nodes.values() {
+            if node.data().role == NodeRole::Contributor {
+                assert_eq!(node.data().slowdown, 3.0);
+            } else {
+                assert_eq!(node.data().slowdown, 1.0);
+            }
+        }
+        assert_eq!(
+            manager.recording.stragglers,
+            manager.recording.initial_contributors
+        );
+
+        // Both bounds of the range can be drawn
+        let mut manager = Manager::default();
+        manager.settings.stragglers = StragglerSettings {
+            fraction: 1.0,
+            slowdown: SlowdownDistribution::Uniform(2.0, 2.0),
+            roles: vec![NodeRole::Contributor],
+        };
+        manager.setup();
+        assert!(manager
+            .nodes
+            .values()
+            .filter(|node| node.data().role == NodeRole::Contributor)
+            .all(|node| node.data().slowdown == 2.0));
+
+        // Slowdowns drawn from a log-normal distribution differ between contributors
+        let mut manager = Manager::default();
+        manager.settings.stragglers = StragglerSettings {
+            fraction: 1.0,
+            slowdown: SlowdownDistribution::LogNormal {
+                mu: 0.5,
+                sigma: 0.5,
+            },
+            roles: vec![NodeRole::Contributor],
+        };
+        manager.setup();
+        let slowdowns = manager
+            .nodes
+            .values()
+            .filter(|node| node.data().role == NodeRole::Contributor)
+            .map(|node| node.data().slowdown)
+            .collect::<Vec<_>>();
+        assert!(slowdowns.iter().all(|&slowdown| slowdown > 0.0));
+        assert!(slowdowns.iter().any(|&slowdown| slowdown != slowdowns[0]));
+    }
+
+    #[test]
+    #[should_panic(expected = "empty range")]
+    fn empty_slowdown_range() {
+        let mut manager = Manager::default();
+        manager.settings.stragglers.fraction = 1.0;
+        manager.settings.stragglers.slowdown = SlowdownDistribution::Uniform(3.0, 2.0);
+        manager.setup();
+    }
+
+    #[test]
+    fn withheld_data_hangs_the_protocol() {
+        let mut manager = Manager::default();
+        manager.settings.omissions = OmissionSettings {
+            fraction: 1.0,
+            behavior: OmissionBehavior::WithholdData,
+        };
+
+        manager.setup();
+        manager.run();
+
+        assert_eq!(manager.recording.outcome(), Outcome::Hung);
+    }
+
+    #[test]
+    fn collection_deadlines() {
+        let mut manager = Manager::default();
+        manager.settings.omissions = OmissionSettings {
+            fraction: 0.2,
+            behavior: OmissionBehavior::WithholdData,
+        };
+        manager.settings.collection_deadlines = vec![8000.0, 6000.0, 4000.0, 2000.0];
+
+        manager.setup();
+        manager.run();
+
+        // The ancestors of the withholding nodes forward what they have
+        assert_eq!(manager.recording.outcome(), Outcome::Incomplete);
+        assert!(manager.recording.expired_deadlines > 0);
+        assert!(manager.recording.missing_children >= manager.recording.omission_faulty);
+        assert!(manager.recording.total_latency < 100000.0);
+    }
+
+    #[test]
+    fn dropped_shares_give_an_incomplete_result() {
+        let mut manager = Manager::default();
+        manager.settings.omissions = OmissionSettings {
+            fraction: 1.0,
+            behavior: OmissionBehavior::DropShares(0.5),
+        };
+
+        manager.setup();
+        manager.run();
+
+        assert_eq!(manager.recording.outcome(), Outcome::Incomplete);
+        assert_ne!(
+            manager.recording.result.as_ref().unwrap().share,
+            manager.recording.expected_result
+        );
+
+        // Every contributor stays alive, the dropped ones are excluded from the result
+        manager.record_contributors();
+        assert!(manager.recording.included_contributors() < manager.recording.initial_contributors);
+        assert_eq!(
+            manager.recording.excluded_contributors.len(),
+            manager.recording.initial_contributors - manager.recording.included_contributors()
+        );
+    }
+
+    #[test]
+    fn consistency_excludes_dropped_shares() {
+        let mut manager = Manager::default();
+        manager.settings.building_blocks.consistency = true;
+        manager.settings.omissions = OmissionSettings {
+            fraction: 0.2,
+            behavior: OmissionBehavior::DropShares(0.5),
+        };
+
+        manager.setup();
+        manager.run();
+
+        // The result only holds the contributions that every member kept
+        let result = manager.recording.result.as_ref().unwrap();
+        assert_eq!(manager.recording.outcome(), Outcome::Incomplete);
+        assert_eq!(result.share, 50.0 * result.contributors.len() as f64);
+        assert!(manager.recording.consistency_mismatches > 0);
+    }
+
+    #[test]
+    fn failures_are_scheduled() {
+        let mut manager = Manager::default();
+        manager.settings.average_failure_time = 5000.0;
+
+        manager.setup();
+
+        let failures = manager
+            .message_queue
+            .iter()
+            .filter(|msg| msg.message_type == MessageType::NodeFailure)
+            .collect::<Vec<_>>();
+        assert_eq!(failures.len(), manager.nodes.len());
+        for msg in failures {
+            assert_eq!(
+                msg.arrival_time,
+                manager.nodes.get(&msg.receiver).unwrap().data().death_time
+            );
+        }
+    }
 }
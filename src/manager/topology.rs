@@ -0,0 +1,214 @@
+use std::error::Error;
+
+use itertools::Itertools;
+
+use crate::{
+    common::Address,
+    node::*,
+    topology::{Topology, TopologyNode},
+};
+
+use super::Manager;
+
+impl Manager {
+    /// Describes the tree built by the manager, as it is after the replacements
+    pub fn export_topology(&self) -> Topology {
+        Topology {
+            nodes: self
+                .nodes
+                .keys()
+                .sorted()
+                .filter(|&&address| self.in_tree(address))
+                .map(|address| {
+                    let data = self.nodes[address].data();
+                    TopologyNode {
+                        role: data.role,
+                        tree_node: data.tree_node.clone(),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether the node still has a position in the tree, replaced nodes are dismissed from it
+    fn in_tree(&self, address: Address) -> bool {
+        let tree_node = &self.nodes[&address].data().tree_node;
+        address == self.querier_address
+            || tree_node.parents.first().is_some_and(|parent| {
+                self.nodes[parent]
+                    .data()
+                    .tree_node
+                    .children
+                    .iter()
+                    .flatten()
+                    .any(|&child| child == address)
+            })
+    }
+
+    /// Creates the nodes of the given topology instead of generating the tree
+    pub(super) fn load_topology(&mut self, topology: &Topology) -> Result<(), Box<dyn Error>> {
+        topology.validate()?;
+
+        let depth = topology
+            .nodes
+            .iter()
+            .filter(|node| node.role != NodeRole::Querier)
+            .map(|node| node.tree_node.depth)
+            .max()
+            .unwrap_or(0);
+        let first_at = |level: u8| {
+            topology.nodes.iter().find(|node| {
+                node.role != NodeRole::Querier && node.tree_node.depth == depth - level
+            })
+        };
+        self.settings.tree.depth = depth;
+        self.settings.tree.group_sizes = (0..depth)
+            .map(|level| {
+                first_at(level).map_or(self.settings.tree.group_size, |node| {
+                    node.tree_node.members.len() as u8
+                })
+            })
+            .collect();
+        // Leaf aggregators have contributors instead of child groups
+        self.settings.tree.fanouts = (0..depth - 1)
+            .map(|level| {
+                first_at(level).map_or(self.settings.tree.fanout, |node| {
+                    node.tree_node.children.len() as u8
+                })
+            })
+            .collect();
+        self.recording.settings = self.settings.clone();
+
+        for TopologyNode { role, tree_node } in topology.nodes.iter().cloned() {
+            let address = tree_node.address;
+            let mut node: Box<dyn Node> = match role {
+                NodeRole::Querier => {
+                    self.querier_address = address;
+                    QuerierNode::new(self.settings.clone(), address)
+                }
+                NodeRole::Aggregator => AggregatorNode::new(self.settings.clone(), address),
+                NodeRole::LeafAggregator => LeafAggregatorNode::new(self.settings.clone(), address),
+                NodeRole::Contributor => {
+                    self.recording.initial_contributors += 1;
+                    ContributorNode::new(self.settings.clone(), address)
+                }
+                role => return Err(format!("Cannot load a node with role {}", role).into()),
+            };
+
+            node.data_mut().leader = tree_node.members[0];
+            node.data_mut().tree_node = tree_node;
+            self.nodes.insert(address, node);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{manager::Outcome, run::FailureHandlingMode};
+
+    use super::*;
+
+    #[test]
+    fn topology_round_trip() {
+        let mut manager = Manager::default();
+        manager.setup();
+        let content = serde_json::to_string(&manager.export_topology()).unwrap();
+
+        let mut loaded = Manager::new(
+            manager.settings.building_blocks.clone(),
+            "another seed".to_string(),
+            manager.settings.tree.clone(),
+        );
+        loaded.topology = Some(Topology::parse(content.as_str()).unwrap());
+        loaded.setup();
+        loaded.run();
+
+        assert_eq!(loaded.export_topology(), manager.export_topology());
+        assert_eq!(
+            loaded.recording.initial_contributors,
+            manager.recording.initial_contributors
+        );
+        assert_eq!(
+            loaded.recording.result.as_ref().unwrap().share,
+            manager.recording.expected_result
+        );
+    }
+
+    #[test]
+    fn load_tree_shape_per_level() {
+        let mut manager = Manager::default();
+        manager.settings.tree.fanouts = vec![2, 3];
+        manager.settings.tree.group_sizes = vec![3, 4, 5];
+        manager.setup();
+
+        let mut loaded = Manager::default();
+        loaded.load_topology(&manager.export_topology()).unwrap();
+        assert_eq!(loaded.settings.tree.fanouts, vec![2, 3]);
+        assert_eq!(loaded.settings.tree.group_sizes, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn reject_inconsistent_topology() {
+        let mut manager = Manager::default();
+        manager.setup();
+        let topology = manager.export_topology();
+        // Addresses are contiguous, each node is found at its address
+        let leaf = topology
+            .nodes
+            .iter()
+            .find(|node| node.role == NodeRole::LeafAggregator)
+            .unwrap()
+            .tree_node
+            .clone();
+        let address = leaf.address;
+
+        let mut replacement = topology.clone();
+        replacement.nodes[address].role = NodeRole::Replacement;
+
+        let mut misplaced = topology.clone();
+        misplaced.nodes[address].tree_node.depth = 2;
+
+        let mut orphan = topology.clone();
+        orphan.nodes[address].tree_node.parents = vec![address];
+
+        let mut split = topology.clone();
+        split.nodes[leaf.members[0]].tree_node.members.pop();
+
+        for topology in [replacement, misplaced, orphan, split] {
+            let mut loaded = Manager::default();
+            assert!(loaded.load_topology(&topology).is_err());
+            assert!(loaded.nodes.is_empty());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed loading topology")]
+    fn abort_on_inconsistent_topology() {
+        let mut manager = Manager::default();
+        manager.topology = Some(Topology::default());
+        manager.setup();
+    }
+
+    #[test]
+    fn export_after_replacement() {
+        let mut manager = Manager::configured(|settings| {
+            settings.building_blocks.failure_handling = FailureHandlingMode::NodeReplacement
+        });
+        manager.kill_node(1, manager.current_time + 1.0);
+        manager.run();
+        assert_eq!(manager.recording.replacements, 1);
+
+        // The dismissed node is left out, its replacement takes its place
+        let topology = manager.export_topology();
+        assert!(topology
+            .nodes
+            .iter()
+            .all(|node| node.tree_node.address != 1));
+        let mut loaded = Manager::default();
+        loaded.topology = Some(topology);
+        loaded.setup();
+        loaded.run();
+        assert_eq!(loaded.recording.outcome(), Outcome::Complete);
+    }
+}
@@ -4,7 +4,7 @@ use crate::common::Address;
 
 use super::{MessageContent, MessageType};
 
-#[derive(PartialEq, PartialOrd, Clone, Debug)]
+#[derive(PartialEq, Clone, Debug)]
 pub struct Message {
     pub delivered: bool,
     pub departure_time: f64,
@@ -53,6 +53,11 @@ impl Message {
             content: MessageContent::default(),
         }
     }
+
+    /// Whether the message is the first hop of a lookup in the overlay
+    pub fn started_lookup(&self) -> bool {
+        self.message_type == MessageType::Lookup && self.content.origin == Some(self.emitter)
+    }
 }
 
 impl Eq for Message {}
@@ -74,6 +79,12 @@ impl Ord for Message {
     }
 }
 
+impl PartialOrd for Message {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Display for Message {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(
@@ -1,11 +1,25 @@
 use std::fmt;
 
-use crate::{common::Address, shares::Share};
+use crate::{common::Address, node::Partition, shares::Share};
+
+use super::Query;
 
 #[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
 pub struct MessageContent {
     pub data: Option<Share>,
     pub target_node: Option<Address>,
+    pub delay: Option<f64>,
+    pub latency: Option<f64>,
+    pub factor: Option<f64>,
+    pub peers: Vec<Address>,
+    pub partition: Option<Partition>,
+    /// Children whose data is included in an aggregate
+    pub children: Vec<Address>,
+    pub query: Option<Query>,
+    /// Point of the overlay ring looked up
+    pub key: Option<u64>,
+    /// Node that started the lookup
+    pub origin: Option<Address>,
 }
 
 impl fmt::Display for MessageContent {
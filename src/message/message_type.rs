@@ -1,6 +1,6 @@
 use std::fmt;
 
-#[derive(Copy, PartialEq, PartialOrd, Debug, Default)]
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug, Default)]
 pub enum MessageType {
     #[default]
     Stop,
@@ -12,6 +12,23 @@ pub enum MessageType {
     ConfirmHealth,
     OpenChannel,
     ConfirmChannel,
+    NodeFailure,
+    SetLinkDelay,
+    SetLinkLatency,
+    SlowDown,
+    StartPartition,
+    SuspectFailure,
+    ProposeChildren,
+    AgreeChildren,
+    ElectLeader,
+    AnnounceLeader,
+    Lookup,
+    LookupResponse,
+    CancelData,
+    CollectionDeadline,
+    SendProvisionalData,
+    ReleaseResult,
+    ReplaceNode,
 }
 
 impl fmt::Display for MessageType {
@@ -20,20 +37,13 @@ impl fmt::Display for MessageType {
     }
 }
 
-impl Clone for MessageType {
-    fn clone(&self) -> Self {
-        match self {
-            &default => default,
-        }
-    }
-}
-
 impl MessageType {
     pub fn priority(self) -> u8 {
         match self {
             MessageType::Stop => 255,
             MessageType::OpenChannel => 255,
             MessageType::ConfirmChannel => 255,
+            MessageType::NodeFailure => 255,
             MessageType::RequestData => 1,
             _ => 0,
         }
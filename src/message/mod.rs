@@ -1,7 +1,10 @@
+#[allow(clippy::module_inception)]
 mod message;
 mod message_content;
 mod message_type;
+mod query;
 
 pub use message::*;
 pub use message_content::*;
 pub use message_type::*;
+pub use query::*;
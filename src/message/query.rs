@@ -0,0 +1,28 @@
+use std::collections::BTreeMap;
+
+/// Profile of a contributor, e.g. its region
+pub type Attributes = BTreeMap<String, String>;
+
+/// Attribute values a contributor must have to be targeted by the query
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct Predicate {
+    pub conditions: Attributes,
+}
+
+impl Predicate {
+    pub fn matches(&self, attributes: &Attributes) -> bool {
+        self.conditions
+            .iter()
+            .all(|(name, value)| attributes.get(name) == Some(value))
+    }
+}
+
+/// Query issued and signed by the querier, disseminated down the tree
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct Query {
+    pub issued_at: f64,
+    /// Privacy budget spent by each contributor answering the query
+    pub epsilon: f64,
+    /// Contributors targeted by the query, all of them when unset
+    pub predicate: Option<Predicate>,
+}
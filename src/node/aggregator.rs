@@ -1,6 +1,4 @@
-use std::collections::HashMap;
-
-use crate::{common::Address, run::RunSettings, tree_node::TreeNode};
+use crate::{common::Address, run::RunSettings};
 
 use super::{Node, NodeData, NodeRole};
 
@@ -10,19 +8,7 @@ pub struct AggregatorNode {
 
 impl Node for AggregatorNode {
     fn new(settings: RunSettings, address: Address) -> Box<AggregatorNode> {
-        let mut data = NodeData {
-            settings,
-            address,
-            role: NodeRole::Replacement,
-            local_time: 0.0,
-            death_time: 0.0,
-            opened_channels: vec![],
-            tree_node: TreeNode::new(address),
-            finished_working: false,
-            aggregates: HashMap::new(),
-            secret_value: 50.0,
-        };
-        data.role = NodeRole::Aggregator;
+        let data = NodeData::new(settings, address, NodeRole::Aggregator);
 
         Box::new(AggregatorNode { data })
     }
@@ -1,11 +1,8 @@
-use std::collections::HashMap;
-
 use crate::{
     common::Address,
     message::{Message, MessageType},
-    run::RunSettings,
+    run::{BudgetPolicy, MismatchPolicy, RunSettings},
     shares::Share,
-    tree_node::TreeNode,
 };
 
 use super::{Node, NodeData, NodeRole};
@@ -13,26 +10,18 @@ use super::{Node, NodeData, NodeRole};
 pub struct ContributorNode {
     data: NodeData,
     shares: Vec<Share>,
+    /// Parents that no longer await the shares
+    cancelled: Vec<Address>,
 }
 
 impl Node for ContributorNode {
     fn new(settings: RunSettings, address: Address) -> Box<ContributorNode> {
-        let data = NodeData {
-            settings,
-            address,
-            role: NodeRole::Contributor,
-            local_time: 0.0,
-            death_time: 0.0,
-            opened_channels: vec![],
-            tree_node: TreeNode::new(address),
-            finished_working: false,
-            aggregates: HashMap::new(),
-            secret_value: 50.0,
-        };
+        let data = NodeData::new(settings, address, NodeRole::Contributor);
 
         Box::new(ContributorNode {
             data,
             shares: vec![],
+            cancelled: vec![],
         })
     }
 
@@ -56,39 +45,87 @@ impl Node for ContributorNode {
         );
         let mut resulting_messages = vec![];
 
-        if self.shares.len() == 0 {
+        if self.shares.is_empty() {
+            // Verify the query
+            self.data.local_time += 3.0 * self.crypto_cost();
+            self.data.query = msg.content.query.clone();
+            let query = self.data.query.as_ref().unwrap();
+
+            // Contributors outside the target of the query do not take part in the result
+            self.data.outside_target = !query
+                .predicate
+                .as_ref()
+                .is_none_or(|predicate| predicate.matches(&self.data.attributes));
+            let mismatch = self
+                .data
+                .settings
+                .targeting
+                .as_ref()
+                .map_or(MismatchPolicy::Neutral, |targeting| targeting.mismatch);
+            if self.data.outside_target && mismatch == MismatchPolicy::Decline {
+                println!("Node #{} is not targeted by the query", msg.receiver);
+                let mut answer = Share::neutral(0.0);
+                // Leaves the MAC of the aggregate untouched
+                if self.data.settings.building_blocks.integrity {
+                    answer.mac = Some(0.0);
+                }
+                self.shares = vec![answer; self.data.tree_node.parents.len()];
+                return self.prepare_data();
+            }
+
+            // Contributors without enough privacy budget left share a neutral value
+            let epsilon = query.epsilon;
+            let refuse = self
+                .data
+                .settings
+                .budget
+                .as_ref()
+                .is_some_and(|budget| budget.policy == BudgetPolicy::Refuse);
+            self.data.declined = refuse
+                && self
+                    .data
+                    .remaining_budget
+                    .is_some_and(|remaining| remaining < epsilon);
+            let value = if self.data.outside_target {
+                0.0
+            } else if self.data.declined {
+                println!("Node #{} has exhausted its privacy budget", msg.receiver);
+                0.0
+            } else {
+                self.data.spent_budget += epsilon;
+                self.data.secret_value
+            };
+
             // Prepare the shares
+            let declined = self.data.declined || self.data.outside_target;
+            let address = self.data.address;
+            let share = |value| match declined {
+                true => Share::neutral(value),
+                false => Share::new(value, address),
+            };
             let mut buffer = 0.0;
-            for _ in 0..(self.data.settings.tree.group_size - 1) {
+            for _ in 0..(self.data.tree_node.parents.len() - 1) {
                 let rng = 10000.0;
                 buffer += rng;
-                self.shares
-                    .push(Share::new(self.data.secret_value + rng, self.data.address));
+                self.shares.push(share(rng));
+            }
+            self.shares.push(share(value - buffer));
+            if let Some(noise) = self.data.noise {
+                self.shares.last_mut().unwrap().add_noise(noise);
             }
-            self.shares.push(Share::new(
-                self.data.secret_value - buffer,
-                self.data.address,
-            ));
 
-            // Verify the query
-            self.data.local_time += 3.0 * self.data().settings.costs.crypto;
-
-            // HACK: Contributors discover their parents when they receive the request.
-            // Here, the knowledge is given by default
-            for parent in &self.data.tree_node.parents {
-                let mut msg = Message::new(
-                    MessageType::PrepareData,
-                    self.data.local_time,
-                    self.data.address,
-                    self.data.local_time + self.message_latency(),
-                    self.data.address,
-                );
-                msg.content.target_node = Some(*parent);
-
-                resulting_messages.push(msg);
+            // Authenticate the shares
+            if self.data.settings.building_blocks.integrity {
+                for share in self.shares.iter_mut() {
+                    share.authenticate();
+                }
+                self.data.local_time += self.shares.len() as f64 * self.crypto_cost();
             }
-        } else {
+
+            resulting_messages.append(&mut self.prepare_data());
+        } else if self.data.tree_node.parents.contains(&msg.emitter) {
             // Request coming from a replacement node
+            resulting_messages.push(self.prepare_share(msg.emitter));
         }
 
         resulting_messages
@@ -100,29 +137,123 @@ impl Node for ContributorNode {
         );
         let mut resulting_messages = vec![];
 
+        let parent = msg.content.target_node.unwrap();
+        let position = self
+            .data
+            .tree_node
+            .parents
+            .iter()
+            .position(|&candidate| candidate == parent);
+        let position = match position {
+            Some(position) if !self.cancelled.contains(&parent) => position,
+            // The parent no longer awaits the share, or was replaced since
+            _ => {
+                println!("Node #{} skips its share to node #{}", msg.receiver, parent);
+                return resulting_messages;
+            }
+        };
+
         let mut response = Message::new(
             MessageType::SendData,
             self.data.local_time,
             self.data.address,
             self.data.local_time + self.message_latency(),
-            msg.content.target_node.unwrap(),
-        );
-        response.content.data = Some(
-            self.shares
-                .get(
-                    self.data
-                        .tree_node
-                        .parents
-                        .iter()
-                        .position(|&parent| parent == msg.content.target_node.unwrap())
-                        .unwrap(),
-                )
-                .unwrap()
-                .clone(),
+            parent,
         );
+        response.content.data = Some(self.shares[position].clone());
 
         resulting_messages.push(response);
 
         resulting_messages
     }
+    fn handle_cancel_data(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} no longer awaits data from node #{}",
+            msg.emitter, msg.receiver
+        );
+        let resulting_messages = vec![];
+
+        self.cancelled.push(msg.emitter);
+
+        resulting_messages
+    }
+}
+
+impl ContributorNode {
+    /// Schedules the sending of the shares to each parent
+    fn prepare_data(&self) -> Vec<Message> {
+        // HACK: Contributors discover their parents when they receive the request.
+        // Here, the knowledge is given by default
+        self.data
+            .tree_node
+            .parents
+            .iter()
+            .map(|&parent| self.prepare_share(parent))
+            .collect()
+    }
+
+    /// Schedules the sending of the share of a parent
+    fn prepare_share(&self, parent: Address) -> Message {
+        let mut msg = Message::new(
+            MessageType::PrepareData,
+            self.data.local_time,
+            self.data.address,
+            self.data.local_time + self.message_latency(),
+            self.data.address,
+        );
+        msg.content.target_node = Some(parent);
+        msg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        manager::{Manager, Outcome},
+        message::{Attributes, Predicate},
+        run::{AttributeSettings, MismatchPolicy, TargetingSettings},
+    };
+
+    #[test]
+    fn targeted_queries() {
+        let run = |mismatch| {
+            let mut manager = Manager::configured(|settings| {
+                settings.building_blocks.integrity = true;
+                settings.targeting = Some(TargetingSettings {
+                    attributes: vec![AttributeSettings {
+                        name: "age".to_string(),
+                        values: vec!["young".to_string(), "old".to_string()],
+                    }],
+                    predicate: Predicate {
+                        conditions: Attributes::from([("age".to_string(), "young".to_string())]),
+                    },
+                    mismatch,
+                });
+            });
+            manager.run();
+            manager
+        };
+
+        let neutral = run(MismatchPolicy::Neutral);
+        let recording = &neutral.recording;
+        assert!(recording.targeted_contributors > 0);
+        assert!(recording.targeted_contributors < recording.initial_contributors);
+        assert_eq!(recording.outcome(), Outcome::Complete);
+        assert!(!recording.tampering_detected());
+        assert_eq!(
+            recording.included_contributors(),
+            recording.targeted_contributors
+        );
+        assert_eq!(
+            recording.result.as_ref().unwrap().share,
+            recording.expected_result
+        );
+
+        // Declining saves the authentication of the shares
+        let decline = run(MismatchPolicy::Decline);
+        assert_eq!(decline.recording.outcome(), Outcome::Complete);
+        assert!(!decline.recording.tampering_detected());
+        assert_eq!(decline.recording.expected_result, recording.expected_result);
+        assert!(decline.recording.total_work < recording.total_work);
+    }
 }
@@ -1,11 +1,4 @@
-use std::collections::HashMap;
-
-use crate::{
-    common::Address,
-    message::{Message, MessageType},
-    run::RunSettings,
-    tree_node::TreeNode,
-};
+use crate::{common::Address, message::Message, run::RunSettings};
 
 use super::{Node, NodeData, NodeRole};
 
@@ -15,18 +8,7 @@ pub struct LeafAggregatorNode {
 
 impl Node for LeafAggregatorNode {
     fn new(settings: RunSettings, address: Address) -> Box<LeafAggregatorNode> {
-        let data = NodeData {
-            settings,
-            address,
-            role: NodeRole::LeafAggregator,
-            local_time: 0.0,
-            death_time: 0.0,
-            opened_channels: vec![],
-            tree_node: TreeNode::new(address),
-            finished_working: false,
-            aggregates: HashMap::new(),
-            secret_value: 50.0,
-        };
+        let data = NodeData::new(settings, address, NodeRole::LeafAggregator);
 
         Box::new(LeafAggregatorNode { data })
     }
@@ -38,36 +20,37 @@ impl Node for LeafAggregatorNode {
         &mut self.data
     }
 
-    fn setup(&mut self, current_time: f64) -> Vec<crate::message::Message> {
-        let mut messages = vec![];
-        messages.push(Message::new(
-            MessageType::ScheduleHealthCheck,
-            current_time,
-            self.data().address,
-            current_time,
-            self.data().address,
-        ));
+    fn handle_request_data(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} received a data request #{}",
+            msg.receiver, msg.emitter
+        );
+        let mut resulting_messages = vec![];
+
+        if self.data.query.is_some() {
+            // The query already reached the node through another member, or the parent was
+            // replaced since
+            resulting_messages.extend(self.resend_aggregate(msg.emitter));
+            return resulting_messages;
+        }
 
-        let is_leader = self
-            .data
-            .tree_node
-            .members
-            .iter()
-            .position(|&member| self.data.address == member)
-            .unwrap()
-            == 0;
-        if is_leader {
+        // Verify the query, the leader then contacts the contributors
+        self.data.local_time += 3.0 * self.crypto_cost();
+        self.data.query = msg.content.query.clone();
+        if self.data.leader == self.data.address {
             for &child in self.data.tree_node.children.iter().flatten() {
-                messages.push(Message::new(
-                    MessageType::RequestData,
-                    current_time,
-                    self.data().address,
-                    current_time + self.message_latency(),
-                    child,
-                ));
+                resulting_messages.push(self.forward_query(child));
             }
+        } else {
+            resulting_messages.push(self.forward_query(self.data.leader));
+        }
+        if self.data.tree_node.children.is_empty() {
+            // Without contributors, there is nothing to wait for
+            resulting_messages.append(&mut self.release_aggregate());
+            return resulting_messages;
         }
+        resulting_messages.extend(self.schedule_deadline());
 
-        messages
+        resulting_messages
     }
 }
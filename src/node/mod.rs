@@ -2,7 +2,9 @@ mod aggregator;
 mod channel;
 mod contributor;
 mod leaf_aggregator;
+#[allow(clippy::module_inception)]
 mod node;
+mod partition;
 mod querier;
 mod role;
 
@@ -11,5 +13,6 @@ pub use channel::*;
 pub use contributor::*;
 pub use leaf_aggregator::*;
 pub use node::*;
+pub use partition::*;
 pub use querier::*;
 pub use role::*;
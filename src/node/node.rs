@@ -1,14 +1,17 @@
+use itertools::Itertools;
 use std::collections::HashMap;
 
 use crate::{
     common::Address,
-    message::{Message, MessageType},
-    run::RunSettings,
+    message::{Attributes, Message, MessageType, Query},
+    overlay::{self, overlay_id},
+    population::Device,
+    run::{ByzantineBehavior, DiscoveryMode, FailureHandlingMode, OmissionBehavior, RunSettings},
     shares::{AggregatableShares, Share},
     tree_node::TreeNode,
 };
 
-use super::{ChannelState, NodeRole};
+use super::{ChannelState, NodeRole, Partition, PartitionMode};
 
 #[derive(Debug)]
 pub struct NodeData {
@@ -18,10 +21,112 @@ pub struct NodeData {
     pub local_time: f64,
     pub death_time: f64,
     pub opened_channels: Vec<ChannelState>,
+    /// Channels planned during the tree construction, opened through messages
+    pub pending_channels: Vec<ChannelState>,
     pub tree_node: TreeNode,
     pub finished_working: bool,
     pub aggregates: HashMap<Address, Share>,
     pub secret_value: f64,
+    pub link_delay: f64,
+    pub link_latencies: HashMap<Address, f64>,
+    pub partitions: HashMap<Address, Partition>,
+    pub slowdown: f64,
+    pub awaited_health_checks: Vec<Address>,
+    pub omission: Option<OmissionBehavior>,
+    pub byzantine: Option<ByzantineBehavior>,
+    pub noise: Option<f64>,
+    pub remaining_budget: Option<f64>,
+    pub spent_budget: f64,
+    pub declined: bool,
+    /// Whether the node refused an aggregate below the release threshold
+    pub rejected_aggregate: bool,
+    /// Contributors whose shares only partly reached the querier, still summed in its result
+    pub partial_contributors: usize,
+    /// Children included by each member of the group, collected by the leader
+    pub proposals: HashMap<Address, Vec<Address>>,
+    /// Whether the leader noticed that the members included different children
+    pub detected_mismatch: bool,
+    /// Children proposed to the leader, until the group agrees on them
+    pub pending_proposal: Option<Vec<Address>>,
+    pub leader: Address,
+    /// Former leaders of the group, suspected to have failed
+    pub dismissed_leaders: Vec<Address>,
+    /// Query received from the parent
+    pub query: Option<Query>,
+    /// Device of the population playing the node
+    pub device: Option<Device>,
+    pub dropped_shares: usize,
+    /// Overlay nodes known by the node, its successor first
+    pub fingers: Vec<Address>,
+    pub attributes: Attributes,
+    /// Whether the contributor does not match the predicate of the query
+    pub outside_target: bool,
+    /// Contributions from the subtree the node waits for before forwarding its aggregate
+    pub quota: Option<usize>,
+    /// Whether the parent no longer awaits data from the node
+    pub cancelled: bool,
+    /// Children left out when the collection deadline expired
+    pub missing_children: usize,
+    /// Children included in the last provisional aggregate sent to the querier
+    pub provisional_children: usize,
+    /// Peers suspected to have failed, no longer monitored
+    pub suspects: Vec<Address>,
+    /// Last aggregate sent and the parent it was sent to, sent again to a replacement parent
+    pub forwarded: Option<(Address, Share)>,
+    /// Suspects to replace, by the overlay key looked up to recruit their replacement
+    pub recruitments: HashMap<u64, Address>,
+    /// First aggregate received, replayed by byzantine nodes
+    pub stale_aggregate: Option<Share>,
+}
+
+impl NodeData {
+    pub fn new(settings: RunSettings, address: Address, role: NodeRole) -> NodeData {
+        NodeData {
+            settings,
+            address,
+            role,
+            local_time: 0.0,
+            death_time: 0.0,
+            opened_channels: vec![],
+            pending_channels: vec![],
+            tree_node: TreeNode::new(address),
+            finished_working: false,
+            aggregates: HashMap::new(),
+            secret_value: 50.0,
+            link_delay: 0.0,
+            link_latencies: HashMap::new(),
+            partitions: HashMap::new(),
+            slowdown: 1.0,
+            awaited_health_checks: vec![],
+            omission: None,
+            byzantine: None,
+            noise: None,
+            remaining_budget: None,
+            spent_budget: 0.0,
+            declined: false,
+            rejected_aggregate: false,
+            partial_contributors: 0,
+            proposals: HashMap::new(),
+            detected_mismatch: false,
+            pending_proposal: None,
+            leader: address,
+            dismissed_leaders: vec![],
+            query: None,
+            device: None,
+            dropped_shares: 0,
+            fingers: vec![],
+            attributes: Attributes::new(),
+            outside_target: false,
+            quota: None,
+            cancelled: false,
+            missing_children: 0,
+            provisional_children: 0,
+            suspects: vec![],
+            forwarded: None,
+            recruitments: HashMap::new(),
+            stale_aggregate: None,
+        }
+    }
 }
 
 pub trait Node {
@@ -33,24 +138,34 @@ pub trait Node {
     fn data_mut(&mut self) -> &mut NodeData;
 
     fn setup(&mut self, current_time: f64) -> Vec<Message> {
-        let mut messages = vec![];
-
-        messages.push(Message::new(
+        vec![Message::new(
             MessageType::ScheduleHealthCheck,
             current_time,
             self.data().address,
             current_time,
             self.data().address,
-        ));
-
-        messages
+        )]
     }
 
     fn handle_message(&mut self, msg: &mut Message) -> Option<Vec<Message>> {
+        if msg.message_type == MessageType::NodeFailure {
+            // The failure is handled even though the node dies at this exact time
+            return Some(self.handle_node_failure(msg));
+        }
         if self.data().death_time <= msg.arrival_time {
             // The node is dead by the time the message arrives
             return Some(vec![]);
         }
+        if let Some(&partition) = self.data().partitions.get(&msg.emitter) {
+            if msg.arrival_time < partition.until {
+                // The message cannot cross the partition
+                if partition.mode == PartitionMode::Hold {
+                    msg.arrival_time = partition.until;
+                    return None;
+                }
+                return Some(vec![]);
+            }
+        }
         if msg.arrival_time < self.data().local_time
             && msg.message_type != MessageType::RequestHealth
         {
@@ -82,32 +197,95 @@ pub trait Node {
             MessageType::SendData => self.handle_send_data(msg),
             MessageType::OpenChannel => self.handle_open_channel(msg),
             MessageType::ConfirmChannel => self.handle_confirm_channel(msg),
+            MessageType::SetLinkDelay => self.handle_set_link_delay(msg),
+            MessageType::SetLinkLatency => self.handle_set_link_latency(msg),
+            MessageType::SlowDown => self.handle_slow_down(msg),
+            MessageType::StartPartition => self.handle_start_partition(msg),
+            MessageType::SuspectFailure => self.handle_suspect_failure(msg),
+            MessageType::ProposeChildren => self.handle_propose_children(msg),
+            MessageType::AgreeChildren => self.handle_agree_children(msg),
+            MessageType::ElectLeader => self.handle_elect_leader(msg),
+            MessageType::AnnounceLeader => self.handle_announce_leader(msg),
+            MessageType::Lookup => self.handle_lookup(msg),
+            MessageType::LookupResponse => self.handle_lookup_response(msg),
+            MessageType::CancelData => self.handle_cancel_data(msg),
+            MessageType::CollectionDeadline => self.handle_collection_deadline(msg),
+            MessageType::SendProvisionalData => self.handle_send_provisional_data(msg),
+            MessageType::ReleaseResult => self.handle_release_result(msg),
+            MessageType::Stop => self.handle_stop(msg),
+            MessageType::ReplaceNode => self.handle_replace_node(msg),
             t => panic!("Unknown message type: {}", t),
         };
 
         // Work of the message = time spent working by the node
         msg.work = self.data().local_time - time_before;
 
+        // Links with a specific latency
+        let resulting_messages = resulting_messages
+            .into_iter()
+            .map(|mut msg| {
+                if let Some(latency) = self.data().link_latencies.get(&msg.receiver) {
+                    msg.arrival_time = msg.departure_time + latency + self.data().link_delay;
+                }
+                msg
+            })
+            .collect();
+
         Some(resulting_messages)
     }
     fn handle_schedule_health_check(&mut self, msg: &mut Message) -> Vec<Message> {
         println!("Node #{} is sending health checks", msg.emitter);
         let mut resulting_messages = vec![];
 
+        // Peers that did not answer the previous health check are suspected
+        let unanswered = std::mem::take(&mut self.data_mut().awaited_health_checks);
+        for &peer in &unanswered {
+            let mut msg = Message::new_timeout(
+                MessageType::SuspectFailure,
+                self.data().address,
+                self.data().local_time,
+                self.data().local_time,
+            );
+            msg.content.target_node = Some(peer);
+            resulting_messages.push(msg);
+        }
+
+        // Suspects cut off by a partition that healed since are monitored again
+        let local_time = self.data().local_time;
+        let healed = self
+            .data()
+            .suspects
+            .iter()
+            .copied()
+            .filter(|peer| {
+                self.data()
+                    .partitions
+                    .get(peer)
+                    .is_some_and(|partition| partition.until <= local_time)
+            })
+            .collect::<Vec<_>>();
+        for peer in healed {
+            self.data_mut().suspects.retain(|&suspect| suspect != peer);
+            self.maintain_channel(peer);
+        }
+
         // Check maintained channels
-        self.data()
+        self.data_mut().awaited_health_checks = self
+            .data()
             .opened_channels
             .iter()
-            .filter(|&channel| channel.maintained)
-            .for_each(|channel| {
-                resulting_messages.push(Message::new(
-                    MessageType::RequestHealth,
-                    self.data().local_time,
-                    self.data().address,
-                    self.data().local_time + self.message_latency(),
-                    channel.peer_address,
-                ))
-            });
+            .filter(|&channel| channel.maintained && !unanswered.contains(&channel.peer_address))
+            .map(|channel| channel.peer_address)
+            .collect();
+        self.data().awaited_health_checks.iter().for_each(|&peer| {
+            resulting_messages.push(Message::new(
+                MessageType::RequestHealth,
+                self.data().local_time,
+                self.data().address,
+                self.data().local_time + self.message_latency(),
+                peer,
+            ))
+        });
 
         // Reschedule
         resulting_messages.push(Message::new_timeout(
@@ -124,17 +302,13 @@ pub trait Node {
             "Node #{} received a health check from node #{}",
             msg.receiver, msg.emitter
         );
-        let mut resulting_messages = vec![];
-
-        resulting_messages.push(Message::new(
+        vec![Message::new(
             MessageType::ConfirmHealth,
             self.data().local_time,
             self.data().address,
             self.data().local_time + self.message_latency(),
             msg.emitter,
-        ));
-
-        resulting_messages
+        )]
     }
     fn handle_confirm_health(&mut self, msg: &mut Message) -> Vec<Message> {
         println!(
@@ -143,6 +317,186 @@ pub trait Node {
         );
         let resulting_messages = vec![];
 
+        self.data_mut()
+            .awaited_health_checks
+            .retain(|&peer| peer != msg.emitter);
+
+        resulting_messages
+    }
+    fn handle_node_failure(&mut self, msg: &mut Message) -> Vec<Message> {
+        let resulting_messages = vec![];
+
+        if self.data().death_time < msg.arrival_time {
+            // The node already died earlier
+            return resulting_messages;
+        }
+
+        println!(
+            "[@{}] Node #{} ({}): Node #{} failed",
+            msg.arrival_time,
+            self.data().address,
+            self.data().role,
+            msg.receiver
+        );
+        msg.delivered = true;
+        self.data_mut().death_time = msg.arrival_time;
+
+        resulting_messages
+    }
+    fn handle_set_link_delay(&mut self, msg: &mut Message) -> Vec<Message> {
+        let resulting_messages = vec![];
+
+        self.data_mut().link_delay += msg.content.delay.unwrap();
+        println!(
+            "Node #{} now delays its messages by {}",
+            msg.receiver,
+            self.data().link_delay
+        );
+
+        resulting_messages
+    }
+    fn handle_set_link_latency(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} now has a latency of {:?} with node #{:?}",
+            msg.receiver, msg.content.latency, msg.content.target_node
+        );
+        let resulting_messages = vec![];
+
+        self.data_mut().link_latencies.insert(
+            msg.content.target_node.unwrap(),
+            msg.content.latency.unwrap(),
+        );
+
+        resulting_messages
+    }
+    fn handle_slow_down(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} is slowed down by a factor {:?}",
+            msg.receiver, msg.content.factor
+        );
+        let resulting_messages = vec![];
+
+        self.data_mut().slowdown = msg.content.factor.unwrap();
+
+        resulting_messages
+    }
+    fn handle_start_partition(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} is partitioned from nodes {:?}: {:?}",
+            msg.receiver, msg.content.peers, msg.content.partition
+        );
+        let resulting_messages = vec![];
+
+        let partition = msg.content.partition.unwrap();
+        for &peer in &msg.content.peers {
+            self.data_mut().partitions.insert(peer, partition);
+        }
+
+        resulting_messages
+    }
+    fn handle_suspect_failure(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} suspects node #{:?} failed",
+            msg.receiver, msg.content.target_node
+        );
+        let mut resulting_messages = vec![];
+
+        // Stop monitoring the suspected node
+        let suspect = msg.content.target_node.unwrap();
+        self.data_mut()
+            .opened_channels
+            .iter_mut()
+            .filter(|channel| channel.peer_address == suspect)
+            .for_each(|channel| channel.maintained = false);
+        self.data_mut().suspects.push(suspect);
+
+        if self.data().tree_node.depth == self.data().settings.tree.depth
+            && self.data().tree_node.parents.contains(&suspect)
+        {
+            // The querier cannot be replaced, the query is abandoned only once its failure is
+            // confirmed, a slow or partitioned querier is still waited for
+            println!("Node #{} waits for the querier", self.data().address);
+            return resulting_messages;
+        }
+
+        if suspect == self.data().leader {
+            // The next member of the group takes over as leader
+            let members = &self.data().tree_node.members;
+            let position = members
+                .iter()
+                .position(|&member| member == suspect)
+                .unwrap();
+            let candidate = members[(position + 1) % members.len()];
+            self.data_mut().dismissed_leaders.push(suspect);
+            self.data_mut().leader = candidate;
+
+            if candidate == self.data().address {
+                let mut msg = Message::new_timeout(
+                    MessageType::ElectLeader,
+                    self.data().address,
+                    self.data().local_time,
+                    self.data().local_time,
+                );
+                msg.content.target_node = Some(suspect);
+                resulting_messages.push(msg);
+            } else {
+                self.maintain_channel(candidate);
+            }
+        } else if self.data().leader == self.data().address {
+            // The group no longer waits for the suspect to agree on the children
+            resulting_messages.append(&mut self.agree_children());
+        }
+
+        if self.data().settings.building_blocks.failure_handling
+            == FailureHandlingMode::NodeReplacement
+        {
+            // Replacements are recruited from the overlay, or through a round trip to a directory
+            let key = overlay::recruitment_key(suspect, self.data().local_time);
+            let lookup = match self.data().settings.discovery {
+                DiscoveryMode::Chord => self.lookup(key),
+                DiscoveryMode::Direct => None,
+            };
+            match lookup {
+                Some(lookup) => {
+                    self.data_mut().recruitments.insert(key, suspect);
+                    resulting_messages.push(lookup);
+                }
+                None => {
+                    resulting_messages.push(self.recruit(suspect, 2.0 * self.message_latency()))
+                }
+            }
+        }
+
+        resulting_messages
+    }
+    fn handle_replace_node(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} recruits a replacement for node #{:?}",
+            msg.receiver, msg.content.target_node
+        );
+        let resulting_messages = vec![];
+
+        resulting_messages
+    }
+    fn handle_stop(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!("Node #{} stops the simulation", msg.receiver);
+        let resulting_messages = vec![];
+
+        resulting_messages
+    }
+    fn handle_send_provisional_data(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} is receiving a provisional aggregate from node #{}",
+            msg.receiver, msg.emitter
+        );
+        let resulting_messages = vec![];
+
+        resulting_messages
+    }
+    fn handle_release_result(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!("Node #{} releases a provisional result", msg.receiver);
+        let resulting_messages = vec![];
+
         resulting_messages
     }
     fn handle_open_channel(&mut self, msg: &mut Message) -> Vec<Message> {
@@ -152,10 +506,8 @@ pub trait Node {
         );
         let mut resulting_messages = vec![];
 
-        self.data_mut()
-            .opened_channels
-            .push(ChannelState::new(msg.emitter, true));
-        self.data_mut().local_time += 3.0 * self.data().settings.costs.crypto;
+        self.accept_channel(msg.emitter);
+        self.data_mut().local_time += 3.0 * self.crypto_cost();
         resulting_messages.push(Message::new(
             MessageType::ConfirmChannel,
             self.data().local_time,
@@ -164,6 +516,11 @@ pub trait Node {
             msg.emitter,
         ));
 
+        // Once reached by its parent, the node builds the rest of its channels
+        if self.data().tree_node.parents.contains(&msg.emitter) {
+            resulting_messages.append(&mut self.open_channels());
+        }
+
         resulting_messages
     }
     fn handle_confirm_channel(&mut self, msg: &mut Message) -> Vec<Message> {
@@ -173,10 +530,8 @@ pub trait Node {
         );
         let resulting_messages = vec![];
 
-        self.data_mut()
-            .opened_channels
-            .push(ChannelState::new(msg.emitter, true));
-        self.data_mut().local_time += 3.0 * self.data().settings.costs.crypto;
+        self.accept_channel(msg.emitter);
+        self.data_mut().local_time += 3.0 * self.crypto_cost();
 
         resulting_messages
     }
@@ -185,7 +540,22 @@ pub trait Node {
             "Node #{} received a data request #{}",
             msg.receiver, msg.emitter
         );
-        let resulting_messages = vec![];
+        let mut resulting_messages = vec![];
+
+        if self.data().query.is_some() || self.data().finished_working {
+            // The query or the data already reached the node, the parent was replaced since
+            return self.resend_aggregate(msg.emitter).into_iter().collect();
+        }
+
+        // Verify the query, then forward it to the children
+        self.data_mut().local_time += 3.0 * self.crypto_cost();
+        self.data_mut().query = msg.content.query.clone();
+        for child_group in self.data().tree_node.children.clone() {
+            for child in self.senders(&child_group) {
+                resulting_messages.push(self.forward_query(child));
+            }
+        }
+        resulting_messages.extend(self.schedule_deadline());
 
         resulting_messages
     }
@@ -198,6 +568,19 @@ pub trait Node {
 
         resulting_messages
     }
+    fn handle_cancel_data(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} no longer awaits data from node #{}",
+            msg.emitter, msg.receiver
+        );
+        let mut resulting_messages = vec![];
+
+        if !self.data().cancelled {
+            resulting_messages.append(&mut self.cancel_children());
+        }
+
+        resulting_messages
+    }
     fn handle_send_data(&mut self, msg: &mut Message) -> Vec<Message> {
         println!(
             "Node #{} is receiving data from node #{}",
@@ -205,61 +588,947 @@ pub trait Node {
         );
         let mut resulting_messages = vec![];
 
-        let aggregate = msg.content.data.clone().unwrap();
+        let mut aggregate = msg.content.data.clone().unwrap();
+        if let Some(OmissionBehavior::DropShares(fraction)) = self.data().omission {
+            let received = self.data().aggregates.len() + 1;
+            if (self.data().dropped_shares + 1) as f64 <= fraction * received as f64 {
+                // The share is silently left out of the aggregate
+                self.data_mut().dropped_shares += 1;
+                aggregate = Share::neutral(0.0);
+            }
+        }
+        if let Some(previous) = self.data().aggregates.get(&msg.emitter) {
+            if previous.count > aggregate.count {
+                // Updates grow over time, this one was overtaken by a later one
+                return resulting_messages;
+            }
+        }
+        if self.data().stale_aggregate.is_none() {
+            self.data_mut().stale_aggregate = Some(aggregate.clone());
+        }
         self.data_mut().aggregates.insert(msg.emitter, aggregate);
+        self.data_mut().local_time += self.compute_cost();
+        if self.data().finished_working {
+            // The aggregate was already forwarded, e.g. at the collection deadline
+            resulting_messages.extend(self.forward_update());
+            return resulting_messages;
+        }
+
+        let children_data = self.children_data();
+        let received_all_data = children_data.iter().all(|(_, data)| data.is_some());
+        let secured = self.data().quota.is_some_and(|quota| {
+            children_data
+                .iter()
+                .filter_map(|(_, data)| data.as_ref())
+                .map(|data| data.count)
+                .sum::<usize>()
+                >= quota
+        });
+        let ready = received_all_data || secured;
+        if ready
+            && self.data().quota.is_some()
+            && self.data().tree_node.depth == self.data().settings.tree.depth
+        {
+            // The top-level group secured the required contributions, the rest of the tree stops
+            resulting_messages.append(&mut self.cancel_children());
+        }
+
+        if ready {
+            resulting_messages.append(&mut self.release_aggregate());
+        } else {
+            resulting_messages.extend(self.forward_provisional_aggregate());
+        }
+
+        resulting_messages
+    }
+    fn handle_collection_deadline(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!("Node #{} reached its collection deadline", msg.receiver);
+        let mut resulting_messages = vec![];
+
+        if !self.data().finished_working {
+            // Whatever was received is forwarded, the missing children are left out
+            self.data_mut().missing_children = self
+                .children_data()
+                .iter()
+                .filter(|(_, data)| data.is_none())
+                .count();
+            resulting_messages.append(&mut self.release_aggregate());
+        }
+
+        resulting_messages
+    }
+
+    fn handle_propose_children(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} received the children included by node #{}",
+            msg.receiver, msg.emitter
+        );
+        let mut resulting_messages = vec![];
+
+        self.data_mut().local_time += self.crypto_cost();
+        self.data_mut()
+            .proposals
+            .insert(msg.emitter, msg.content.children.clone());
+
+        resulting_messages.append(&mut self.agree_children());
+
+        resulting_messages
+    }
+    fn handle_agree_children(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} received the agreed children from node #{}",
+            msg.receiver, msg.emitter
+        );
+        let mut resulting_messages = vec![];
+
+        self.data_mut().local_time += self.crypto_cost();
+        self.data_mut().pending_proposal = None;
+        resulting_messages.push(self.forward_aggregate(&msg.content.children));
+
+        resulting_messages
+    }
+    fn handle_elect_leader(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} replaces node #{:?} as the leader of its group",
+            msg.receiver, msg.content.target_node
+        );
+        let mut resulting_messages = vec![];
 
-        let expected_data = self
+        // The proposal sent to the former leader is lost
+        let former_leader = msg.content.target_node.unwrap();
+        self.data_mut().proposals.remove(&former_leader);
+
+        self.data_mut().local_time += self.crypto_cost();
+        for member in self.data().tree_node.members.clone() {
+            if member == self.data().address || self.data().dismissed_leaders.contains(&member) {
+                continue;
+            }
+            self.maintain_channel(member);
+            resulting_messages.push(Message::new(
+                MessageType::AnnounceLeader,
+                self.data().local_time,
+                self.data().address,
+                self.data().local_time + self.message_latency(),
+                member,
+            ));
+        }
+        if self.data().pending_proposal.is_some() {
+            resulting_messages.push(self.propose_children());
+        }
+
+        // HACK: This handler should be implemented for each role
+        if self.data().role == NodeRole::LeafAggregator && self.data().query.is_some() {
+            // Contributors that never answered may not have received the query
+            for &child in self.data().tree_node.children.iter().flatten() {
+                if !self.data().aggregates.contains_key(&child) {
+                    resulting_messages.push(self.forward_query(child));
+                }
+            }
+        }
+
+        resulting_messages
+    }
+    fn handle_announce_leader(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} acknowledges node #{} as the leader of its group",
+            msg.receiver, msg.emitter
+        );
+        let mut resulting_messages = vec![];
+
+        self.data_mut().local_time += self.crypto_cost();
+        if self.data().leader != msg.emitter {
+            let former_leader = self.data().leader;
+            self.data_mut().dismissed_leaders.push(former_leader);
+            self.data_mut().leader = msg.emitter;
+            self.maintain_channel(msg.emitter);
+        }
+        // The proposal sent to the former leader is lost
+        if self.data().pending_proposal.is_some() {
+            resulting_messages.push(self.propose_children());
+        }
+
+        resulting_messages
+    }
+
+    fn handle_lookup(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} received a lookup from node #{:?}",
+            msg.receiver, msg.content.origin
+        );
+        let mut resulting_messages = vec![];
+
+        if msg.content.target_node == Some(self.data().address) {
+            // The node is responsible for the key, it answers the origin directly
+            let mut response = Message::new(
+                MessageType::LookupResponse,
+                self.data().local_time,
+                self.data().address,
+                self.data().local_time + self.message_latency(),
+                msg.content.origin.unwrap(),
+            );
+            response.content.key = msg.content.key;
+            resulting_messages.push(response);
+        } else {
+            let (key, origin) = (msg.content.key.unwrap(), msg.content.origin.unwrap());
+            resulting_messages.extend(self.route_lookup(key, origin));
+        }
+
+        resulting_messages
+    }
+    fn handle_lookup_response(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} found node #{} in the overlay",
+            msg.receiver, msg.emitter
+        );
+        let mut resulting_messages = vec![];
+
+        // Group members are looked up to open a channel with them
+        if self
+            .data()
+            .pending_channels
+            .iter()
+            .any(|channel| channel.peer_address == msg.emitter)
+        {
+            resulting_messages.push(Message::new(
+                MessageType::OpenChannel,
+                self.data().local_time,
+                self.data().address,
+                self.data().local_time + self.message_latency(),
+                msg.emitter,
+            ));
+        }
+
+        // The node responsible for a recruitment key names the replacement
+        let key = msg.content.key.unwrap();
+        if let Some(suspect) = self.data_mut().recruitments.remove(&key) {
+            resulting_messages.push(self.recruit(suspect, 0.0));
+        }
+
+        resulting_messages
+    }
+
+    /// Opens the planned channels with the peers below the node in the tree
+    fn open_channels(&mut self) -> Vec<Message> {
+        let peers = self
+            .data()
+            .pending_channels
+            .iter()
+            .map(|channel| channel.peer_address)
+            .filter(|&peer| peer > self.data().address)
+            .collect::<Vec<_>>();
+
+        match self.data().settings.discovery {
+            // Discovering the group members takes a round trip
+            DiscoveryMode::Direct => {
+                self.data_mut().local_time += 2.0 * self.message_latency();
+                peers
+                    .into_iter()
+                    .map(|peer| {
+                        Message::new(
+                            MessageType::OpenChannel,
+                            self.data().local_time,
+                            self.data().address,
+                            self.data().local_time + self.message_latency(),
+                            peer,
+                        )
+                    })
+                    .collect()
+            }
+            DiscoveryMode::Chord => peers
+                .into_iter()
+                .flat_map(|peer| self.lookup(overlay_id(peer)))
+                .collect(),
+        }
+    }
+
+    /// Starts a lookup in the overlay for the node responsible for the key
+    fn lookup(&self, key: u64) -> Option<Message> {
+        self.route_lookup(key, self.data().address)
+    }
+
+    /// Sends a lookup to the next node on its way to the key
+    fn route_lookup(&self, key: u64, origin: Address) -> Option<Message> {
+        let (next, responsible) =
+            overlay::next_hop(self.data().address, &self.data().fingers, key)?;
+
+        let mut forwarded = Message::new(
+            MessageType::Lookup,
+            self.data().local_time,
+            self.data().address,
+            self.data().local_time + self.message_latency(),
+            next,
+        );
+        forwarded.content.key = Some(key);
+        forwarded.content.origin = Some(origin);
+        forwarded.content.target_node = responsible.then_some(next);
+
+        Some(forwarded)
+    }
+
+    /// Moves the channel with a peer from the planned channels to the opened ones
+    fn accept_channel(&mut self, peer: Address) {
+        let channel = match self
+            .data()
+            .pending_channels
+            .iter()
+            .position(|channel| channel.peer_address == peer)
+        {
+            Some(index) => self.data_mut().pending_channels.remove(index),
+            None => ChannelState::new(peer, true),
+        };
+        self.data_mut().opened_channels.push(channel);
+    }
+
+    /// Forwards the query received from the parent to a child
+    fn forward_query(&self, child: Address) -> Message {
+        let mut msg = Message::new(
+            MessageType::RequestData,
+            self.data().local_time,
+            self.data().address,
+            self.data().local_time + self.message_latency(),
+            child,
+        );
+        msg.content.query = self.data().query.clone();
+
+        msg
+    }
+
+    /// Sends the pending proposal to the group leader
+    fn propose_children(&mut self) -> Message {
+        self.data_mut().local_time += self.crypto_cost();
+        let mut msg = Message::new(
+            MessageType::ProposeChildren,
+            self.data().local_time,
+            self.data().address,
+            self.data().local_time + self.message_latency(),
+            self.data().leader,
+        );
+        msg.content.children = self.data().pending_proposal.clone().unwrap();
+
+        msg
+    }
+
+    /// Forwards the aggregate of the children received so far, or proposes them to the leader
+    fn release_aggregate(&mut self) -> Vec<Message> {
+        let mut resulting_messages = vec![];
+        self.data_mut().finished_working = true;
+
+        let children_data = self.children_data();
+        if self.data().omission == Some(OmissionBehavior::WithholdData) {
+            println!("Node #{} withholds its aggregate", self.data().address);
+        } else if self.data().settings.building_blocks.consistency {
+            // Propose the children included in the aggregate to the group leader
+            self.data_mut().pending_proposal = Some(
+                children_data
+                    .iter()
+                    .filter(|(_, data)| data.as_ref().is_some_and(|data| data.count > 0))
+                    .map(|(child, _)| *child)
+                    .collect(),
+            );
+            resulting_messages.push(self.propose_children());
+        } else {
+            let received = children_data
+                .iter()
+                .filter(|(_, data)| data.is_some())
+                .map(|(child, _)| *child)
+                .collect::<Vec<_>>();
+            resulting_messages.push(self.forward_aggregate(&received));
+        }
+
+        resulting_messages
+    }
+
+    /// Once every member still trusted proposed its children, the leader tells the group which
+    /// children to include
+    fn agree_children(&mut self) -> Vec<Message> {
+        let mut resulting_messages = vec![];
+
+        let members = self
             .data()
             .tree_node
-            .children
+            .members
             .iter()
-            .map(|child_group| {
-                // HACK: This handler should be implemented for each role
-                if self.data().role == NodeRole::LeafAggregator {
-                    // The child is a contributor
-                    self.data().aggregates.get(&child_group[0])
-                } else {
-                    let position = self
-                        .data()
-                        .tree_node
-                        .members
-                        .iter()
-                        .position(|&member| self.data().address == member)
-                        .unwrap();
-                    self.data().aggregates.get(&child_group[position])
-                }
+            .copied()
+            .unique()
+            .filter(|member| {
+                !self.data().dismissed_leaders.contains(member)
+                    && !self.data().suspects.contains(member)
             })
             .collect::<Vec<_>>();
-        let received_all_data = expected_data.iter().all(|data| data.is_some());
-        if received_all_data {
-            let position = self
-                .data()
-                .tree_node
-                .members
+        if !self.data().proposals.contains_key(&self.data().address)
+            || members
                 .iter()
-                .position(|&member| self.data().address == member)
-                .unwrap();
+                .any(|member| !self.data().proposals.contains_key(member))
+        {
+            return resulting_messages;
+        }
+        let own = self.data().proposals[&self.data().address].clone();
+        let proposals = members
+            .iter()
+            .map(|member| self.data().proposals[member].clone())
+            .collect::<Vec<_>>();
+        self.data_mut().proposals.clear();
+
+        // Only the children included by every member are kept
+        let agreed = own
+            .iter()
+            .copied()
+            .filter(|child| proposals.iter().all(|children| children.contains(child)))
+            .collect::<Vec<_>>();
+        if proposals.iter().any(|children| children != &agreed) {
+            println!(
+                "Node #{} detected that its group included different children",
+                self.data().address
+            );
+            self.data_mut().detected_mismatch = true;
+        }
+
+        self.data_mut().local_time += self.crypto_cost();
+        for member in members {
             let mut msg = Message::new(
-                MessageType::SendData,
+                MessageType::AgreeChildren,
                 self.data().local_time,
                 self.data().address,
                 self.data().local_time + self.message_latency(),
-                *self.data().tree_node.parents.get(position).unwrap(),
-            );
-            msg.content.data = Some(
-                expected_data
-                    .iter()
-                    .map(|&x| x.unwrap().clone())
-                    .collect::<Vec<_>>()
-                    .aggregate(),
+                member,
             );
+            msg.content.children = agreed.clone();
             resulting_messages.push(msg);
         }
 
         resulting_messages
     }
 
+    /// Sends the parent the aggregate again when more children answered after the quota was
+    /// reached, making up for the subtrees that dropped out
+    fn forward_update(&mut self) -> Option<Message> {
+        if self.data().quota.is_none()
+            || self.data().cancelled
+            || self.data().tree_node.depth == self.data().settings.tree.depth
+            || self.data().omission == Some(OmissionBehavior::WithholdData)
+            || self.data().settings.building_blocks.consistency
+        {
+            // Agreed or top-level aggregates are final
+            return None;
+        }
+
+        let received = self
+            .children_data()
+            .into_iter()
+            .filter(|(_, data)| data.is_some())
+            .map(|(child, _)| child)
+            .collect::<Vec<_>>();
+        Some(self.forward_aggregate(&received))
+    }
+
+    /// Tells the children that their data, or their updates, are no longer awaited
+    fn cancel_children(&mut self) -> Vec<Message> {
+        self.data_mut().cancelled = true;
+
+        // Contributors that already sent their share are done
+        let leaf = self.data().role == NodeRole::LeafAggregator;
+        self.data()
+            .tree_node
+            .children
+            .iter()
+            .flat_map(|child_group| self.senders(child_group))
+            .filter(|child| !leaf || !self.data().aggregates.contains_key(child))
+            .map(|child| {
+                Message::new(
+                    MessageType::CancelData,
+                    self.data().local_time,
+                    self.data().address,
+                    self.data().local_time + self.message_latency(),
+                    child,
+                )
+            })
+            .collect()
+    }
+
+    /// Sends the querier the aggregate of the children received so far, when the top-level group
+    /// reports progressively
+    fn forward_provisional_aggregate(&mut self) -> Option<Message> {
+        let fraction = self.data().settings.progressive_release?;
+        if self.data().tree_node.depth != self.data().settings.tree.depth
+            || self.data().omission == Some(OmissionBehavior::WithholdData)
+        {
+            return None;
+        }
+
+        let children_data = self.children_data();
+        let received = children_data
+            .iter()
+            .filter(|(_, data)| data.is_some())
+            .map(|(child, _)| *child)
+            .collect::<Vec<_>>();
+        let contributions = children_data
+            .iter()
+            .flat_map(|(_, data)| data.as_ref())
+            .map(|data| data.count)
+            .sum::<usize>();
+        if received.len() == self.data().provisional_children
+            || (received.len() as f64) < fraction * children_data.len() as f64
+            || contributions < self.data().settings.release_threshold
+        {
+            return None;
+        }
+        self.data_mut().provisional_children = received.len();
+
+        let mut msg = self.forward_aggregate(&received);
+        msg.message_type = MessageType::SendProvisionalData;
+        msg.content.children = received;
+
+        Some(msg)
+    }
+
+    /// Sets the timeout after which the node stops waiting for its children
+    fn schedule_deadline(&self) -> Option<Message> {
+        let level = match self.data().role {
+            NodeRole::Querier => 0,
+            _ => (self.data().settings.tree.depth - self.data().tree_node.depth) as usize + 1,
+        };
+        let deadline = self.data().settings.collection_deadline(level)?;
+
+        Some(Message::new_timeout(
+            MessageType::CollectionDeadline,
+            self.data().address,
+            self.data().local_time,
+            self.data().local_time + deadline,
+        ))
+    }
+
+    /// Data received from each child group, identified by its first member
+    fn children_data(&self) -> Vec<(Address, Option<Share>)> {
+        self.data()
+            .tree_node
+            .children
+            .iter()
+            .filter_map(|child_group| {
+                let senders = self.senders(child_group);
+                if senders.is_empty() {
+                    // A smaller child group does not send to every member
+                    return None;
+                }
+                let data = senders
+                    .iter()
+                    .map(|sender| self.data().aggregates.get(sender).cloned())
+                    .collect::<Option<Vec<_>>>()
+                    .map(|shares| shares.aggregate());
+                Some((child_group[0], data))
+            })
+            .collect()
+    }
+
+    /// Members of a child group that send their data to the node
+    fn senders(&self, child_group: &[Address]) -> Vec<Address> {
+        // HACK: This handler should be implemented for each role
+        if self.data().role == NodeRole::LeafAggregator {
+            // The child is a contributor
+            return vec![child_group[0]];
+        }
+
+        let group_size = self.data().tree_node.members.len();
+        child_group
+            .iter()
+            .enumerate()
+            .filter(|(position, _)| position % group_size == self.position())
+            .map(|(_, &child)| child)
+            .collect()
+    }
+
+    /// Parent to which the node sends its data, groups may differ in size
+    fn parent(&self) -> Address {
+        let parents = &self.data().tree_node.parents;
+        parents[self.position() % parents.len()]
+    }
+
+    /// Sends the aggregate of the given children to the parent
+    fn forward_aggregate(&mut self, children: &[Address]) -> Message {
+        let mut msg = Message::new(
+            MessageType::SendData,
+            self.data().local_time,
+            self.data().address,
+            self.data().local_time + self.message_latency(),
+            self.parent(),
+        );
+        let shares = self
+            .children_data()
+            .into_iter()
+            .filter(|(child, _)| children.contains(child))
+            .map(|(_, data)| data.unwrap())
+            .collect::<Vec<_>>();
+        let mut aggregate = match self.data().byzantine {
+            Some(ByzantineBehavior::AddOffset(offset)) => {
+                let mut aggregate = shares.aggregate();
+                aggregate.share += offset;
+                aggregate
+            }
+            // Without any data received yet, there is nothing to replay or to drop
+            Some(ByzantineBehavior::Replay) => self
+                .data()
+                .stale_aggregate
+                .clone()
+                .unwrap_or_else(|| shares.aggregate()),
+            Some(ByzantineBehavior::DropChild) if !shares.is_empty() => {
+                shares[1..].to_vec().aggregate()
+            }
+            _ => shares.aggregate(),
+        };
+        if aggregate.count < self.data().settings.release_threshold {
+            // Too few contributions, a neutral aggregate is forwarded instead
+            println!(
+                "Node #{} rejects an aggregate of {} contributions",
+                self.data().address,
+                aggregate.count
+            );
+            self.data_mut().rejected_aggregate = true;
+            aggregate = Share {
+                mac: aggregate.mac.map(|_| 0.0),
+                ..Share::neutral(0.0)
+            };
+        }
+        if let Some(noise) = self.data().noise {
+            aggregate.add_noise(noise);
+        }
+        self.data_mut().forwarded = Some((msg.receiver, aggregate.clone()));
+        msg.content.data = Some(aggregate);
+
+        msg
+    }
+
+    /// Monitors the given peer through the health checks
+    fn maintain_channel(&mut self, peer: Address) {
+        match self
+            .data_mut()
+            .opened_channels
+            .iter_mut()
+            .find(|channel| channel.peer_address == peer)
+        {
+            Some(channel) => channel.maintained = true,
+            None => self
+                .data_mut()
+                .opened_channels
+                .push(ChannelState::new(peer, true)),
+        }
+    }
+
+    /// Sends the last aggregate again to a parent replacing the one it was sent to
+    fn resend_aggregate(&self, parent: Address) -> Option<Message> {
+        let (receiver, aggregate) = self.data().forwarded.clone()?;
+        if !self.data().finished_working
+            || receiver == parent
+            || !self.data().tree_node.parents.contains(&parent)
+        {
+            return None;
+        }
+        let mut msg = Message::new(
+            MessageType::SendData,
+            self.data().local_time,
+            self.data().address,
+            self.data().local_time + self.message_latency(),
+            parent,
+        );
+        msg.content.data = Some(aggregate);
+
+        Some(msg)
+    }
+
+    /// Replaces the suspect once the recruited node joined, after the time spent discovering it
+    fn recruit(&self, suspect: Address, discovery: f64) -> Message {
+        // The recruited node checks its credentials and opens the channels of its position
+        let joining = 10.0 * self.crypto_cost() + 6.0 * self.message_latency();
+        let mut msg = Message::new_timeout(
+            MessageType::ReplaceNode,
+            self.data().address,
+            self.data().local_time,
+            self.data().local_time + discovery + joining,
+        );
+        msg.content.target_node = Some(suspect);
+
+        msg
+    }
+
+    /// Takes the place of a suspected node, the children are asked for their data again
+    fn join(&mut self, current_time: f64) -> Vec<Message> {
+        self.data_mut().local_time = current_time;
+        let mut messages = self.setup(current_time);
+
+        if self.data().query.is_some() {
+            for child_group in self.data().tree_node.children.clone() {
+                for child in self.senders(&child_group) {
+                    messages.push(self.forward_query(child));
+                }
+            }
+            if self.data().tree_node.children.is_empty() {
+                messages.append(&mut self.release_aggregate());
+            } else {
+                messages.extend(self.schedule_deadline());
+            }
+        }
+
+        messages
+    }
+
+    /// Position of the node in its group
+    fn position(&self) -> usize {
+        self.data()
+            .tree_node
+            .members
+            .iter()
+            .position(|&member| self.data().address == member)
+            .unwrap()
+    }
+
     fn message_latency(&self) -> f64 {
-        self.data().settings.costs.comm
+        self.data().settings.costs.comm * self.data().slowdown + self.data().link_delay
+    }
+    fn crypto_cost(&self) -> f64 {
+        self.data().settings.costs.crypto * self.data().slowdown
+    }
+    fn compute_cost(&self) -> f64 {
+        self.data().settings.costs.compute * self.data().slowdown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        manager::{Manager, Outcome},
+        message::MessageType,
+        node::NodeRole,
+        run::{
+            BuildingBlocks, ByzantineBehavior, ByzantineSettings, NoiseLocation, NoiseMechanism,
+            NoiseSettings, SlowdownDistribution, StragglerSettings,
+        },
+        shares::AggregatableShares,
+    };
+
+    #[test]
+    fn leader_election() {
+        for consistency in [false, true] {
+            let mut manager = Manager::configured(|settings| {
+                settings.building_blocks = BuildingBlocks::resilient();
+                settings.building_blocks.consistency = consistency;
+            });
+
+            // Node #1 leads the group [1, 2, 3] and dies before any aggregate is ready
+            manager.kill_node(1, manager.current_time + 1.0);
+            manager.run();
+
+            // The new leader carries on while a replacement takes the place of node #1
+            assert_eq!(manager.recording.leader_elections, 1);
+            assert_eq!(manager.nodes[&3].data().leader, 2);
+            assert_eq!(manager.recording.outcome(), Outcome::Complete);
+        }
+    }
+
+    #[test]
+    fn leaf_leader_election() {
+        let mut manager =
+            Manager::configured(|settings| settings.building_blocks = BuildingBlocks::resilient());
+
+        // The leader dies before asking the contributors for their data
+        let leader = manager
+            .nodes
+            .values()
+            .map(|node| node.data())
+            .filter(|data| data.role == NodeRole::LeafAggregator)
+            .map(|data| data.leader)
+            .min()
+            .unwrap();
+        manager.kill_node(leader, manager.current_time + 1.0);
+        manager.run();
+
+        // The next member asks them instead
+        let successor = manager.nodes[&leader].data().tree_node.members[1];
+        assert_eq!(manager.recording.leader_elections, 1);
+        assert!(manager.recording.sent_messages.iter().any(|msg| {
+            msg.message_type == MessageType::RequestData && msg.emitter == successor
+        }));
+        assert_eq!(manager.recording.outcome(), Outcome::Complete);
+    }
+
+    #[test]
+    fn agree_without_dead_member() {
+        let mut manager =
+            Manager::configured(|settings| settings.building_blocks.consistency = true);
+
+        // Node #3 dies before proposing the children it included to its leader, node #1
+        manager.kill_node(3, manager.current_time + 1.0);
+        manager.run();
+
+        // The leader agrees with the remaining member once node #3 is suspected
+        let sent = |emitter, message_type| {
+            manager
+                .recording
+                .sent_messages
+                .iter()
+                .any(|msg| msg.emitter == emitter && msg.message_type == message_type)
+        };
+        assert!(sent(1, MessageType::AgreeChildren));
+        assert!(sent(1, MessageType::SendData));
+        assert!(sent(2, MessageType::SendData));
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let mut manager = Manager::configured(|settings| {
+            settings.building_blocks.integrity = true;
+            settings.byzantines = ByzantineSettings {
+                fraction: 0.2,
+                behavior: ByzantineBehavior::AddOffset(1000.0),
+            };
+        });
+        manager.run();
+
+        assert!(manager.recording.byzantines > 0);
+        assert!(manager.recording.corrupted());
+        assert!(manager.recording.tampering_detected());
+    }
+
+    #[test]
+    fn dropped_child_is_not_detected() {
+        let mut manager = Manager::configured(|settings| {
+            settings.building_blocks.integrity = true;
+            settings.byzantines = ByzantineSettings {
+                fraction: 0.2,
+                behavior: ByzantineBehavior::DropChild,
+            };
+        });
+        manager.run();
+
+        assert!(manager.recording.corrupted());
+        assert!(!manager.recording.tampering_detected());
+    }
+
+    #[test]
+    fn replayed_aggregate_is_stale() {
+        let mut manager = Manager::configured(|_| {});
+        manager.nodes.get_mut(&1).unwrap().data_mut().byzantine = Some(ByzantineBehavior::Replay);
+        manager.run();
+
+        // Node #1 forwards the first aggregate of its children instead of their sum
+        let node = manager.nodes[&1].data();
+        let fresh = node
+            .aggregates
+            .values()
+            .cloned()
+            .collect::<Vec<_>>()
+            .aggregate();
+        let replayed = manager
+            .recording
+            .sent_messages
+            .iter()
+            .find(|msg| msg.emitter == 1 && msg.message_type == MessageType::SendData)
+            .and_then(|msg| msg.content.data.clone())
+            .unwrap();
+        assert_eq!(Some(&replayed), node.stale_aggregate.as_ref());
+        assert_ne!(replayed.share, fresh.share);
+        assert!(manager.recording.corrupted());
+    }
+
+    #[test]
+    fn misbehaving_without_data() {
+        for behavior in [ByzantineBehavior::Replay, ByzantineBehavior::DropChild] {
+            let mut manager = Manager::configured(|settings| {
+                settings.byzantines = ByzantineSettings {
+                    fraction: 1.0,
+                    behavior,
+                };
+                // Leaf aggregators forward their aggregate before any share arrives
+                settings.collection_deadlines = vec![8000.0, 6000.0, 4000.0, 1.0];
+            });
+            manager.run();
+
+            assert!(manager.recording.expired_deadlines > 0);
+            assert!(manager.recording.total_latency < 100000.0);
+        }
+    }
+
+    #[test]
+    fn distributed_noise() {
+        for location in [NoiseLocation::Contributors, NoiseLocation::LeafAggregators] {
+            let mut manager = Manager::configured(|settings| {
+                // Only the noise of the contributors is authenticated
+                settings.building_blocks.integrity = location == NoiseLocation::Contributors;
+                settings.noise = Some(NoiseSettings {
+                    mechanism: NoiseMechanism::Gaussian,
+                    location,
+                    epsilon: 1.0,
+                    delta: 1e-5,
+                    sensitivity: 100.0,
+                    dropout_tolerance: 0.0,
+                });
+            });
+            manager.run();
+
+            assert_eq!(manager.recording.outcome(), Outcome::Complete);
+            assert_eq!(manager.recording.spent_epsilon(), Some(1.0));
+            assert!(manager.recording.accuracy_error().unwrap() > 0.0);
+            assert!(!manager.recording.corrupted());
+            assert!(!manager.recording.tampering_detected());
+        }
+    }
+
+    #[test]
+    fn over_provisioning() {
+        let run = |required| {
+            let mut manager = Manager::configured(|settings| {
+                settings.stragglers = StragglerSettings {
+                    fraction: 0.3,
+                    slowdown: SlowdownDistribution::Fixed(20.0),
+                    roles: vec![NodeRole::Contributor],
+                };
+                settings.required_contributions = required;
+            });
+            manager.run();
+            manager
+        };
+
+        let baseline = run(None);
+        let required = baseline.recording.initial_contributors / 2;
+        let manager = run(Some(required));
+        let recording = &manager.recording;
+
+        assert_eq!(recording.outcome(), Outcome::Complete);
+        assert!(recording.included_contributors() >= required);
+        assert!(recording.provisioning_overhead().unwrap() >= 1.0);
+        // The stragglers are no longer awaited
+        assert!(recording.cancelled_shares > 0);
+        assert!(recording.total_latency < baseline.recording.total_latency);
+    }
+
+    #[test]
+    fn over_provisioning_absorbs_dropouts() {
+        let mut manager =
+            Manager::configured(|settings| settings.required_contributions = Some(100));
+        assert!(manager.recording.initial_contributors >= 150);
+
+        // Most contributors of the largest leaf group drop out before sharing their data
+        let leaf = manager
+            .nodes
+            .values()
+            .map(|node| node.data())
+            .filter(|data| data.role == NodeRole::LeafAggregator)
+            .max_by_key(|data| (data.tree_node.children.len(), data.address))
+            .unwrap();
+        let contributors = leaf
+            .tree_node
+            .children
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        let dropouts = contributors.len() * 6 / 10;
+        assert!(dropouts as f64 > contributors.len() as f64 - leaf.quota.unwrap() as f64);
+        for &contributor in &contributors[..dropouts] {
+            manager.kill_node(contributor, manager.current_time + 1.0);
+        }
+        manager.run();
+
+        // The other leaf groups make up for the missing contributions
+        let recording = &manager.recording;
+        assert_eq!(recording.outcome(), Outcome::Complete);
+        assert!(recording.included_contributors() >= 100);
     }
 }
@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionMode {
+    /// Messages crossing the partition are lost
+    #[default]
+    Drop,
+    /// Messages crossing the partition are delivered once it ends
+    Hold,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Partition {
+    pub until: f64,
+    pub mode: PartitionMode,
+}
+
+impl Partition {
+    pub fn new(until: f64, mode: PartitionMode) -> Partition {
+        Partition { until, mode }
+    }
+}
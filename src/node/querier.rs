@@ -1,29 +1,31 @@
 use std::collections::HashMap;
 
-use crate::{common::Address, message::Message, run::RunSettings, tree_node::TreeNode};
+use crate::{
+    common::Address,
+    message::{Message, MessageType, Query},
+    run::RunSettings,
+    shares::{AggregatableShares, Share},
+};
 
 use super::{Node, NodeData, NodeRole};
 
 pub struct QuerierNode {
     data: NodeData,
+    /// Last provisional aggregate of each member of the child groups, and the children it includes
+    provisional: HashMap<Address, (Vec<Address>, Share)>,
+    /// Contributors included in the last provisional result
+    released_contributors: usize,
 }
 
 impl Node for QuerierNode {
     fn new(settings: RunSettings, address: Address) -> Box<QuerierNode> {
-        let data = NodeData {
-            settings,
-            address,
-            role: NodeRole::Querier,
-            local_time: 0.0,
-            death_time: 0.0,
-            opened_channels: vec![],
-            tree_node: TreeNode::new(address),
-            finished_working: false,
-            aggregates: HashMap::new(),
-            secret_value: 50.0,
-        };
-
-        Box::new(QuerierNode { data })
+        let data = NodeData::new(settings, address, NodeRole::Querier);
+
+        Box::new(QuerierNode {
+            data,
+            provisional: HashMap::new(),
+            released_contributors: 0,
+        })
     }
 
     fn data(&self) -> &NodeData {
@@ -33,6 +35,36 @@ impl Node for QuerierNode {
         &mut self.data
     }
 
+    fn setup(&mut self, current_time: f64) -> Vec<Message> {
+        let mut messages = vec![];
+        messages.push(Message::new(
+            MessageType::ScheduleHealthCheck,
+            current_time,
+            self.data.address,
+            current_time,
+            self.data.address,
+        ));
+
+        // Sign the query and send it to every member of the child groups
+        self.data.local_time = current_time + self.crypto_cost();
+        self.data.query = Some(Query {
+            issued_at: current_time,
+            epsilon: self.data.settings.query_epsilon(),
+            predicate: self
+                .data
+                .settings
+                .targeting
+                .as_ref()
+                .map(|targeting| targeting.predicate.clone()),
+        });
+        for &child in self.data.tree_node.children.iter().flatten() {
+            messages.push(self.forward_query(child));
+        }
+        messages.extend(self.schedule_deadline());
+
+        messages
+    }
+
     fn handle_send_data(&mut self, msg: &mut Message) -> Vec<Message> {
         println!(
             "Node #{} is receiving data from node #{}",
@@ -42,34 +74,239 @@ impl Node for QuerierNode {
 
         let aggregate = msg.content.data.clone().unwrap();
         self.data_mut().aggregates.insert(msg.emitter, aggregate);
+        self.data_mut().local_time += self.compute_cost();
 
-        let expected_data = self
+        // The result is reconstructed from the shares of every member of the child groups
+        let received_all_data = self
             .data()
             .tree_node
             .children
             .iter()
-            .map(|child_group| {
-                // HACK: This handler should be implemented for each role
-                if self.data().role == NodeRole::LeafAggregator {
-                    // The child is a contributor
-                    self.data().aggregates.get(&child_group[0])
-                } else {
-                    let position = self
-                        .data()
-                        .tree_node
-                        .members
+            .flatten()
+            .all(|child| self.data().aggregates.contains_key(child));
+        if received_all_data && !self.data().finished_working {
+            resulting_messages.append(&mut self.release_result());
+        }
+
+        resulting_messages
+    }
+    fn handle_send_provisional_data(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!(
+            "Node #{} is receiving a provisional aggregate from node #{}",
+            msg.receiver, msg.emitter
+        );
+        let mut resulting_messages = vec![];
+
+        let aggregate = msg.content.data.clone().unwrap();
+        self.provisional
+            .insert(msg.emitter, (msg.content.children.clone(), aggregate));
+        self.data.local_time += self.compute_cost();
+        if !self.data.finished_working {
+            resulting_messages.extend(self.release_provisional_result());
+        }
+
+        resulting_messages
+    }
+    fn handle_collection_deadline(&mut self, msg: &mut Message) -> Vec<Message> {
+        println!("Node #{} reached its collection deadline", msg.receiver);
+        let mut resulting_messages = vec![];
+
+        if !self.data().finished_working {
+            resulting_messages.append(&mut self.release_result());
+        }
+
+        resulting_messages
+    }
+}
+
+impl QuerierNode {
+    /// Reconstructs the result from the child groups whose members all answered
+    fn release_result(&mut self) -> Vec<Message> {
+        let mut resulting_messages = vec![];
+
+        let groups = self.data.tree_node.children.len();
+        self.data.missing_children = groups - self.complete_groups().len();
+
+        println!("Finished! Propagating stop...");
+        let mut msg = Message::new_timeout(
+            MessageType::Stop,
+            self.data.address,
+            self.data.local_time,
+            self.data.local_time,
+        );
+        let (result, contributions) = self.reconstruct();
+        if contributions < self.data.settings.release_threshold {
+            println!(
+                "Result of {} contributions is below the release threshold",
+                contributions
+            );
+            self.data.rejected_aggregate = true;
+        } else {
+            msg.content.data = Some(result);
+        }
+        resulting_messages.push(msg);
+        self.data.finished_working = true;
+
+        resulting_messages
+    }
+
+    /// Releases a provisional result once the members of each child group sent aggregates of
+    /// the same children, and each time these include more contributors
+    fn release_provisional_result(&mut self) -> Option<Message> {
+        let mut shares = vec![];
+        for group in &self.data.tree_node.children {
+            if group
+                .iter()
+                .all(|member| self.data.aggregates.contains_key(member))
+            {
+                shares.extend(
+                    group
                         .iter()
-                        .position(|&member| self.data().address == member)
-                        .unwrap();
-                    self.data().aggregates.get(&child_group[position])
-                }
+                        .map(|member| self.data.aggregates[member].clone()),
+                );
+                continue;
+            }
+            // The shares of a group only add up when its members included the same children
+            let provisional = group
+                .iter()
+                .map(|member| self.provisional.get(member))
+                .collect::<Option<Vec<_>>>()?;
+            if provisional
+                .iter()
+                .any(|(children, _)| children != &provisional[0].0)
+            {
+                return None;
+            }
+            shares.extend(provisional.into_iter().map(|(_, share)| share.clone()));
+        }
+
+        let mut result = shares.aggregate();
+        let group_size = self.data.settings.tree.leaf_group_size();
+        result
+            .contributors
+            .retain(|_, &mut shares| shares == group_size);
+        if result.contributors.len() <= self.released_contributors
+            || result.contributors.len() < self.data.settings.release_threshold
+        {
+            return None;
+        }
+        self.released_contributors = result.contributors.len();
+
+        println!(
+            "Releasing a provisional result of {} contributions",
+            result.contributors.len()
+        );
+        let mut msg = Message::new_timeout(
+            MessageType::ReleaseResult,
+            self.data.address,
+            self.data.local_time,
+            self.data.local_time,
+        );
+        msg.content.data = Some(result);
+
+        Some(msg)
+    }
+
+    /// Child groups whose members all answered
+    fn complete_groups(&self) -> Vec<Vec<Address>> {
+        self.data
+            .tree_node
+            .children
+            .iter()
+            .filter(|group| {
+                group
+                    .iter()
+                    .all(|child| self.data.aggregates.contains_key(child))
             })
+            .cloned()
+            .collect()
+    }
+
+    /// Result made of the complete child groups, and the number of contributions it includes
+    fn reconstruct(&mut self) -> (Share, usize) {
+        // The shares of a group are useless without those of its other members
+        let shares = self
+            .complete_groups()
+            .into_iter()
+            .flatten()
+            .map(|child| self.data.aggregates[&child].clone())
             .collect::<Vec<_>>();
-        let received_all_data = expected_data.iter().all(|data| data.is_some());
-        if received_all_data {
-            println!("Finished! Propagating stop...");
+
+        if self.data.settings.building_blocks.integrity {
+            // Each release verifies the shares it is made of
+            self.data.local_time += shares.len() as f64 * self.crypto_cost();
+            if shares.iter().any(|data| !data.verify()) {
+                println!("Tampering detected!");
+            }
         }
 
-        resulting_messages
+        let mut result = shares.aggregate();
+        // A value is only reconstructed when all its shares reached the querier. The other
+        // shares cannot be told apart in the sum, so they are counted instead.
+        let group_size = self.data.settings.tree.leaf_group_size();
+        let received = result.contributors.len();
+        result
+            .contributors
+            .retain(|_, &mut shares| shares == group_size);
+        self.data.partial_contributors = received - result.contributors.len();
+        // Shares of partially received contributions do not count towards the threshold
+        let contributions = result.contributors.len();
+
+        (result, contributions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        manager::{Manager, Outcome},
+        node::PartitionMode,
+    };
+
+    #[test]
+    fn querier_failure() {
+        let mut manager = Manager::configured(|_| {});
+        assert_eq!(
+            manager.nodes[&manager.querier_address]
+                .data()
+                .tree_node
+                .members,
+            vec![manager.querier_address]
+        );
+
+        // The top-level group notices the querier is gone and abandons the query
+        manager.kill_node(manager.querier_address, manager.current_time + 1.0);
+        manager.run();
+
+        assert_eq!(manager.recording.outcome(), Outcome::QuerierFailed);
+        assert!(manager.recording.suspicions > 0);
+        assert!(manager.recording.total_latency < 100000.0);
+    }
+
+    #[test]
+    fn unreachable_querier_is_waited_for() {
+        for slow in [false, true] {
+            let mut manager = Manager::configured(|_| {});
+            let querier = manager.querier_address;
+            let start = manager.current_time;
+
+            // The top-level group suspects the querier, which is still alive
+            if slow {
+                manager.slow_down(querier, 20.0, start);
+            } else {
+                let top_group = manager.nodes[&querier].data().tree_node.children[0].clone();
+                manager.partition(
+                    &[querier],
+                    &top_group,
+                    start,
+                    start + 5000.0,
+                    PartitionMode::Hold,
+                );
+            }
+            manager.run();
+
+            assert!(manager.recording.false_suspicions > 0);
+            assert_eq!(manager.recording.outcome(), Outcome::Complete);
+        }
     }
 }
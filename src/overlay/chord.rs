@@ -0,0 +1,123 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+
+use crate::common::Address;
+
+/// Position of a node on the ring, derived from its address as with consistent hashing
+pub fn overlay_id(address: Address) -> u64 {
+    mix(address as u64)
+}
+
+/// Point of the ring looked up to recruit a replacement for a node
+pub fn recruitment_key(suspect: Address, time: f64) -> u64 {
+    mix(overlay_id(suspect) ^ time.to_bits())
+}
+
+/// SplitMix64 finalizer, spreading the identifiers over the ring
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Next node of a lookup for the key, and whether that node is responsible for the key.
+/// The fingers start with the successor of the node.
+pub fn next_hop(address: Address, fingers: &[Address], key: u64) -> Option<(Address, bool)> {
+    let id = overlay_id(address);
+    let distance = |other: u64| other.wrapping_sub(id);
+    let &successor = fingers.first()?;
+
+    if distance(key) <= distance(overlay_id(successor)) {
+        return Some((successor, true));
+    }
+    // Closest finger preceding the key
+    fingers
+        .iter()
+        .filter(|&&finger| distance(overlay_id(finger)) < distance(key))
+        .max_by_key(|&&finger| distance(overlay_id(finger)))
+        .map(|&finger| (finger, false))
+}
+
+/// Chord ring made of the nodes of the simulation, stabilized before the query starts
+#[derive(Debug, Default)]
+pub struct Chord {
+    ring: Vec<(u64, Address)>,
+    fingers: HashMap<Address, Vec<Address>>,
+}
+
+impl Chord {
+    pub fn new(addresses: impl IntoIterator<Item = Address>) -> Chord {
+        let mut chord = Chord {
+            ring: addresses
+                .into_iter()
+                .map(|address| (overlay_id(address), address))
+                .sorted()
+                .collect(),
+            fingers: HashMap::new(),
+        };
+        chord.fingers = chord
+            .ring
+            .iter()
+            .map(|&(id, address)| {
+                let fingers = (0..64)
+                    .map(|i| chord.successor(id.wrapping_add(1 << i)))
+                    .filter(|&finger| finger != address)
+                    .unique()
+                    .collect();
+                (address, fingers)
+            })
+            .collect();
+
+        chord
+    }
+
+    /// Node responsible for the key
+    pub fn successor(&self, key: u64) -> Address {
+        let index = self.ring.partition_point(|&(id, _)| id < key);
+        self.ring[index % self.ring.len()].1
+    }
+
+    /// Finger table of a node, its successor first
+    pub fn fingers(&self, address: Address) -> &[Address] {
+        &self.fingers[&address]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Nodes visited by a lookup for the key started at the given node
+    fn route(chord: &Chord, from: Address, key: u64) -> Vec<Address> {
+        let mut hops = vec![];
+        let mut current = from;
+        while let Some((next, responsible)) = next_hop(current, chord.fingers(current), key) {
+            hops.push(next);
+            if responsible {
+                break;
+            }
+            current = next;
+        }
+
+        hops
+    }
+
+    #[test]
+    fn lookups_reach_the_responsible_node() {
+        let chord = Chord::new(0..200);
+
+        let mut hops = vec![];
+        for from in [0, 17, 199] {
+            for key in [0, u64::MAX / 3, overlay_id(42), overlay_id(42) + 1] {
+                let route = route(&chord, from, key);
+                assert_eq!(*route.last().unwrap(), chord.successor(key));
+                hops.push(route.len());
+            }
+        }
+        assert_eq!(route(&chord, 0, overlay_id(42)).last(), Some(&42));
+        // Logarithmic in the number of nodes
+        let average = hops.iter().sum::<usize>() as f64 / hops.len() as f64;
+        assert!(average < (200_f64).log2());
+    }
+}
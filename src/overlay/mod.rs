@@ -0,0 +1,3 @@
+mod chord;
+
+pub use chord::*;
@@ -0,0 +1,119 @@
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use std::fmt;
+
+// The binary runs without a population, its tests generate each class
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub enum DeviceClass {
+    Phone,
+    Desktop,
+    Server,
+}
+
+impl fmt::Display for DeviceClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Share of the population made of a class of devices, and how they behave
+#[derive(Clone, Debug)]
+pub struct DeviceProfile {
+    pub class: DeviceClass,
+    pub fraction: f64,
+    pub slowdown: f64,
+    /// Multiplier of the average failure time
+    pub reliability: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct PopulationSettings {
+    pub size: usize,
+    pub profiles: Vec<DeviceProfile>,
+    pub regions: usize,
+    /// Classes of devices allowed to aggregate, any device may when empty
+    pub aggregator_classes: Vec<DeviceClass>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Device {
+    pub id: usize,
+    pub class: DeviceClass,
+    pub slowdown: f64,
+    pub reliability: f64,
+    pub region: usize,
+}
+
+/// Devices from which the nodes of each query are sampled
+#[derive(Clone, Debug, Default)]
+pub struct Population {
+    pub devices: Vec<Device>,
+}
+
+impl Population {
+    pub fn generate<R: Rng>(settings: &PopulationSettings, rng: &mut R) -> Population {
+        let profiles =
+            WeightedIndex::new(settings.profiles.iter().map(|profile| profile.fraction)).unwrap();
+
+        Population {
+            devices: (0..settings.size)
+                .map(|id| {
+                    let profile = &settings.profiles[profiles.sample(rng)];
+                    Device {
+                        id,
+                        class: profile.class,
+                        slowdown: profile.slowdown,
+                        reliability: profile.reliability,
+                        region: rng.gen_range(0..settings.regions.max(1)),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn generate_population() {
+        let settings = PopulationSettings {
+            size: 1000,
+            profiles: vec![
+                DeviceProfile {
+                    class: DeviceClass::Phone,
+                    fraction: 0.7,
+                    slowdown: 2.0,
+                    reliability: 0.5,
+                },
+                DeviceProfile {
+                    class: DeviceClass::Desktop,
+                    fraction: 0.2,
+                    slowdown: 1.5,
+                    reliability: 2.0,
+                },
+                DeviceProfile {
+                    class: DeviceClass::Server,
+                    fraction: 0.1,
+                    slowdown: 1.0,
+                    reliability: 10.0,
+                },
+            ],
+            regions: 4,
+            aggregator_classes: vec![],
+        };
+        let population = Population::generate(&settings, &mut SmallRng::seed_from_u64(0));
+
+        let phones = population
+            .devices
+            .iter()
+            .filter(|device| device.class == DeviceClass::Phone)
+            .count();
+        assert_eq!(population.devices.len(), 1000);
+        assert!(phones > 600 && phones < 800);
+        assert!(population.devices.iter().all(|device| device.region < 4));
+    }
+}
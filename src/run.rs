@@ -1,9 +1,17 @@
 use std::fmt;
 
+use crate::{
+    message::{Attributes, Predicate},
+    node::NodeRole,
+    population::PopulationSettings,
+};
+
 #[derive(Default, Clone, Debug, PartialEq)]
 pub enum FailureHandlingMode {
     #[default]
     FullFailurePropagation,
+    /// Not implemented yet, failures propagate as with `FullFailurePropagation`
+    #[allow(dead_code)]
     LocalFailurePropagation,
     NodeReplacement,
 }
@@ -17,6 +25,10 @@ impl fmt::Display for FailureHandlingMode {
 #[derive(Default, Clone, Debug)]
 pub struct BuildingBlocks {
     pub failure_handling: FailureHandlingMode,
+    /// Shares are authenticated so that the querier detects tampering
+    pub integrity: bool,
+    /// Group members agree on the children included before forwarding their aggregate
+    pub consistency: bool,
 }
 
 impl BuildingBlocks {
@@ -27,16 +39,28 @@ impl BuildingBlocks {
     pub fn minimal() -> BuildingBlocks {
         BuildingBlocks {
             failure_handling: FailureHandlingMode::FullFailurePropagation,
+            integrity: false,
+            consistency: false,
         }
     }
+
+    // Unused until local failure propagation is implemented
+    #[allow(dead_code)]
     pub fn tolerant() -> BuildingBlocks {
         BuildingBlocks {
             failure_handling: FailureHandlingMode::LocalFailurePropagation,
+            integrity: false,
+            consistency: false,
         }
     }
+
+    // The binary runs the minimal building blocks, the replacement tests this preset
+    #[cfg_attr(not(test), allow(dead_code))]
     pub fn resilient() -> BuildingBlocks {
         BuildingBlocks {
             failure_handling: FailureHandlingMode::NodeReplacement,
+            integrity: false,
+            consistency: false,
         }
     }
 }
@@ -48,11 +72,250 @@ pub struct CostsSettings {
     pub compute: f64,
 }
 
+// The binary draws from the fanout range, the tree shape tests from the others
+#[derive(Default, Clone, Debug, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub enum ContributorsDistribution {
+    /// Drawn uniformly from [fanout, fanout²)
+    #[default]
+    FanoutRange,
+    Fixed(usize),
+    /// Drawn uniformly from [low, high]
+    Uniform(usize, usize),
+    Poisson(f64),
+}
+
 #[derive(Default, Clone, Debug)]
 pub struct TreeSettings {
     pub fanout: u8,
     pub depth: u8,
     pub group_size: u8,
+    /// Fanout of each aggregator level from the root, `fanout` is used for missing levels
+    pub fanouts: Vec<u8>,
+    /// Group size of each aggregator level from the root, `group_size` is used for missing levels
+    pub group_sizes: Vec<u8>,
+    /// Number of contributors below each leaf aggregator group
+    pub contributors: ContributorsDistribution,
+    /// Exact number of contributors, spread evenly over the leaves
+    pub target_contributors: Option<usize>,
+}
+
+impl TreeSettings {
+    pub fn fanout_at(&self, level: usize) -> u8 {
+        *self.fanouts.get(level).unwrap_or(&self.fanout)
+    }
+
+    pub fn group_size_at(&self, level: usize) -> u8 {
+        *self.group_sizes.get(level).unwrap_or(&self.group_size)
+    }
+
+    /// Number of leaf aggregator groups of a generated tree
+    pub fn leaf_groups(&self) -> usize {
+        (0..self.depth as usize - 1)
+            .map(|level| self.fanout_at(level) as usize)
+            .product()
+    }
+
+    /// Number of shares each contributor splits its value into
+    pub fn leaf_group_size(&self) -> usize {
+        self.group_size_at(self.depth as usize - 1) as usize
+    }
+}
+
+// Stragglers are disabled in the binary, the straggler tests draw from each distribution
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub enum SlowdownDistribution {
+    Fixed(f64),
+    /// Drawn uniformly from [low, high]
+    Uniform(f64, f64),
+    LogNormal {
+        mu: f64,
+        sigma: f64,
+    },
+}
+
+impl Default for SlowdownDistribution {
+    fn default() -> Self {
+        SlowdownDistribution::Fixed(1.0)
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct StragglerSettings {
+    /// Probability for a node to be a straggler
+    pub fraction: f64,
+    /// Factor applied to the costs and latency of a straggler
+    pub slowdown: SlowdownDistribution,
+    /// Roles of the nodes that can be stragglers, all of them when empty
+    pub roles: Vec<NodeRole>,
+}
+
+// Omissions are disabled in the binary, the omission tests cover each behavior
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub enum OmissionBehavior {
+    /// Never forwards its aggregate
+    #[default]
+    WithholdData,
+    /// Leaves a fraction of the received shares out of its aggregate
+    DropShares(f64),
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct OmissionSettings {
+    /// Probability for an aggregator to be omission-faulty
+    pub fraction: f64,
+    pub behavior: OmissionBehavior,
+}
+
+// The binary has no byzantine aggregators, the integrity tests cover each behavior
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub enum ByzantineBehavior {
+    /// Adds an offset to its aggregate
+    AddOffset(f64),
+    /// Forwards a stale aggregate, the first one it received, instead of the fresh one
+    Replay,
+    /// Leaves the data of its first child out of its aggregate
+    DropChild,
+}
+
+#[derive(Clone, Debug)]
+pub struct ByzantineSettings {
+    /// Probability for an aggregator to be byzantine
+    pub fraction: f64,
+    pub behavior: ByzantineBehavior,
+}
+
+impl Default for ByzantineSettings {
+    fn default() -> Self {
+        ByzantineSettings {
+            fraction: 0.0,
+            behavior: ByzantineBehavior::AddOffset(0.0),
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct CollusionSettings {
+    /// Probability for a node to be compromised, the analysis is skipped when 0
+    pub compromised_fraction: f64,
+    /// Number of random coalitions drawn to estimate the exposure
+    pub draws: usize,
+}
+
+// The binary adds no noise, the noise tests calibrate both mechanisms
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub enum NoiseMechanism {
+    #[default]
+    Laplace,
+    Gaussian,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum NoiseLocation {
+    /// Each contributor adds noise to its value before sharing it
+    #[default]
+    Contributors,
+    /// Each leaf aggregator group adds noise to its aggregate, which the aggregators cannot
+    /// authenticate
+    LeafAggregators,
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct NoiseSettings {
+    pub mechanism: NoiseMechanism,
+    pub location: NoiseLocation,
+    pub epsilon: f64,
+    pub delta: f64,
+    pub sensitivity: f64,
+    /// Fraction of the noise contributions that can be lost while still guaranteeing epsilon
+    pub dropout_tolerance: f64,
+}
+
+impl NoiseSettings {
+    /// Scale of the total noise: b for Laplace, sigma for Gaussian
+    pub fn scale(&self) -> f64 {
+        match self.mechanism {
+            NoiseMechanism::Laplace => self.sensitivity / self.epsilon,
+            NoiseMechanism::Gaussian => {
+                self.sensitivity * (2.0 * (1.25 / self.delta).ln()).sqrt() / self.epsilon
+            }
+        }
+    }
+
+    /// Epsilon actually spent when only `included` of the `required` noise contributions made it to the result
+    pub fn spent_epsilon(&self, included: usize, required: usize) -> f64 {
+        let fraction = (included as f64 / required as f64).min(1.0);
+        match self.mechanism {
+            NoiseMechanism::Laplace => self.epsilon / fraction,
+            NoiseMechanism::Gaussian => self.epsilon / fraction.sqrt(),
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum BudgetPolicy {
+    /// Budgets are only tracked
+    #[default]
+    Track,
+    /// Contributors refuse requests once their budget is exhausted
+    Refuse,
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct BudgetSettings {
+    /// Privacy budget of each contributor over all the rounds
+    pub budget: f64,
+    /// Budget spent by each query when no noise is configured
+    pub query_cost: f64,
+    pub policy: BudgetPolicy,
+    /// Number of queries run over the same contributors
+    pub rounds: usize,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum MismatchPolicy {
+    /// Contributors outside the target share a neutral value, indistinguishable from a real one
+    #[default]
+    Neutral,
+    /// Contributors outside the target send an empty answer whose zero MAC leaves the aggregate untouched
+    Decline,
+}
+
+/// Attribute of the contributors, drawn uniformly among its values
+#[derive(Clone, Debug)]
+pub struct AttributeSettings {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct TargetingSettings {
+    /// Attributes of the contributors, besides the region of their device
+    pub attributes: Vec<AttributeSettings>,
+    pub predicate: Predicate,
+    pub mismatch: MismatchPolicy,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum ConstructionMode {
+    /// The tree is built for free, its latency is estimated
+    #[default]
+    Estimated,
+    /// Members are discovered and channels opened through messages
+    Simulated,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum DiscoveryMode {
+    /// Peers are found with a round trip to a directory
+    #[default]
+    Direct,
+    /// Peers are found through lookups in a Chord overlay made of the nodes
+    Chord,
 }
 
 #[derive(Default, Clone, Debug)]
@@ -61,12 +324,114 @@ pub struct RunSettings {
     pub average_failure_time: f64,
     pub health_check_period: f64,
     pub costs: CostsSettings,
+    pub stragglers: StragglerSettings,
+    pub omissions: OmissionSettings,
+    pub byzantines: ByzantineSettings,
+    pub collusion: CollusionSettings,
+    pub noise: Option<NoiseSettings>,
+    pub budget: Option<BudgetSettings>,
+    pub targeting: Option<TargetingSettings>,
+    /// Minimum number of contributions an aggregate needs to be forwarded or released
+    pub release_threshold: usize,
+    /// Contributions the querier asks for out of the recruited contributors, all of them are
+    /// awaited when unset
+    pub required_contributions: Option<usize>,
+    /// Time each node waits for its children once it received the query, from the querier (0)
+    /// down to the leaf aggregators. Nodes of the missing levels wait until all their children
+    /// answered.
+    pub collection_deadlines: Vec<f64>,
+    /// Fraction of the children of the top-level group after which the querier releases
+    /// provisional results, refined as more children answer
+    pub progressive_release: Option<f64>,
+    pub construction: ConstructionMode,
+    pub discovery: DiscoveryMode,
+    /// Devices from which the nodes are sampled, nodes are abstract when unset
+    pub population: Option<PopulationSettings>,
     pub tree: TreeSettings,
     pub seed: String,
 }
 
 impl RunSettings {
+    /// Privacy budget spent by a contributor taking part in the query
+    pub fn query_epsilon(&self) -> f64 {
+        match (&self.noise, &self.budget) {
+            (Some(noise), _) => noise.epsilon,
+            (None, Some(budget)) => budget.query_cost,
+            (None, None) => 0.0,
+        }
+    }
+
+    /// Collection deadline of the nodes of a level, the querier being at level 0
+    pub fn collection_deadline(&self, level: usize) -> Option<f64> {
+        self.collection_deadlines.get(level).copied()
+    }
+
+    /// Whether a contributor with the given attributes is targeted by the query
+    pub fn targets(&self, attributes: &Attributes) -> bool {
+        self.targeting
+            .as_ref()
+            .is_none_or(|targeting| targeting.predicate.matches(attributes))
+    }
+
+    /// Checks the settings that cannot be used as they are
+    pub fn validate(&self) -> Result<(), String> {
+        if let ContributorsDistribution::Uniform(low, high) = self.tree.contributors {
+            if low > high {
+                return Err(format!(
+                    "The contributors per leaf are drawn from an empty range [{}, {}]",
+                    low, high
+                ));
+            }
+        }
+        if let Some(target) = self.tree.target_contributors {
+            if target < self.tree.leaf_groups() {
+                return Err(format!(
+                    "{} contributors cannot be spread over {} leaf groups",
+                    target,
+                    self.tree.leaf_groups()
+                ));
+            }
+        }
+        if let Some(noise) = &self.noise {
+            if noise.location == NoiseLocation::LeafAggregators && self.building_blocks.integrity {
+                return Err(
+                    "Noise added by the leaf aggregators is unauthenticated, it fails \
+                    the integrity check"
+                        .to_string(),
+                );
+            }
+        }
+        if let SlowdownDistribution::Uniform(low, high) = self.stragglers.slowdown {
+            if low > high {
+                return Err(format!(
+                    "The slowdown of stragglers is drawn from an empty range [{}, {}]",
+                    low, high
+                ));
+            }
+        }
+        Ok(())
+    }
+
     pub fn tree_construction_latency(&self) -> f64 {
         (self.tree.depth as f64) * self.costs.crypto * 4.0 + self.costs.comm * 2.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unauthenticated_leaf_noise() {
+        let mut settings = RunSettings::default();
+        settings.building_blocks.integrity = true;
+        settings.noise = Some(NoiseSettings::default());
+        assert!(settings.validate().is_ok());
+
+        settings.noise = Some(NoiseSettings {
+            location: NoiseLocation::LeafAggregators,
+            ..NoiseSettings::default()
+        });
+        assert!(settings.validate().unwrap_err().contains("unauthenticated"));
+    }
+}
@@ -0,0 +1,6 @@
+#[allow(clippy::module_inception)]
+mod scenario;
+mod selector;
+
+pub use scenario::*;
+pub use selector::*;
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs};
+
+use crate::node::PartitionMode;
+
+use super::Selector;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScenarioAction {
+    /// Kill the selected nodes
+    Kill { at: f64, target: Selector },
+    /// Add a delay to every message sent by the selected nodes
+    Delay {
+        from: f64,
+        #[serde(default)]
+        until: Option<f64>,
+        delay: f64,
+        target: Selector,
+    },
+    /// Multiply the costs and latency of the selected nodes
+    SlowDown {
+        at: f64,
+        factor: f64,
+        target: Selector,
+    },
+    /// Set the latency of the links between the selected nodes and their peers
+    LinkLatency {
+        at: f64,
+        latency: f64,
+        target: Selector,
+        peers: Selector,
+    },
+    /// Drop or hold back the messages between the selected nodes and their peers
+    Partition {
+        from: f64,
+        until: f64,
+        #[serde(default)]
+        mode: PartitionMode,
+        target: Selector,
+        peers: Selector,
+    },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    pub actions: Vec<ScenarioAction>,
+}
+
+impl Scenario {
+    pub fn from_path(path: &str) -> Result<Scenario, Box<dyn Error>> {
+        Scenario::parse(fs::read_to_string(path)?.as_str())
+    }
+
+    pub fn parse(content: &str) -> Result<Scenario, Box<dyn Error>> {
+        Ok(serde_json::from_str(content)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_scenario() {
+        let scenario = Scenario::parse(
+            r#"{
+                "actions": [
+                    { "type": "kill", "at": 850, "target": { "depth": 2, "group": 0, "member": 0 } },
+                    { "type": "delay", "from": 1000, "delay": 500, "target": { "depth": 1, "group": 1, "subtree": true } }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            scenario.actions,
+            vec![
+                ScenarioAction::Kill {
+                    at: 850.0,
+                    target: Selector {
+                        depth: 2,
+                        group: 0,
+                        member: Some(0),
+                        subtree: false
+                    }
+                },
+                ScenarioAction::Delay {
+                    from: 1000.0,
+                    until: None,
+                    delay: 500.0,
+                    target: Selector {
+                        depth: 1,
+                        group: 1,
+                        member: None,
+                        subtree: true
+                    }
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+/// Selects nodes by their position in the tree rather than by their address,
+/// so that scenarios stay valid when the address numbering changes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Selector {
+    /// Depth of the nodes in the tree as in `TreeNode`, leaf aggregators being at depth 1 and
+    /// contributors at depth 0
+    pub depth: u8,
+    /// Index of the group among the groups of that depth, from left to right
+    pub group: usize,
+    /// Position of the node in its group, every member is selected when absent
+    #[serde(default)]
+    pub member: Option<usize>,
+    /// Also select every node of the descendant groups
+    #[serde(default)]
+    pub subtree: bool,
+}
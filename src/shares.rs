@@ -1,24 +1,63 @@
-use std::{
-    collections::hash_map::DefaultHasher,
-    fmt,
-    hash::{Hash, Hasher},
-};
+use std::{collections::BTreeMap, fmt};
 
 use crate::common::Address;
 
+/// Secret key of the querier used to authenticate the shares, unknown to the aggregators
+const MAC_KEY: f64 = 7.0;
+
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct Share {
     pub share: f64,
     pub count: usize,
-    pub id: String,
+    /// Number of shares of each contributor included in the share
+    pub contributors: BTreeMap<Address, usize>,
+    pub mac: Option<f64>,
+    /// Differential privacy noise included in the share, only known by the simulator
+    pub noise: f64,
+    /// Number of noise contributions included in the share
+    pub noises: usize,
 }
 
 impl Share {
+    /// Share of a contributor that takes no part in the result
+    pub fn neutral(value: f64) -> Share {
+        Share {
+            share: value,
+            count: 0,
+            contributors: BTreeMap::new(),
+            mac: None,
+            noise: 0.0,
+            noises: 0,
+        }
+    }
+
     pub fn new(value: f64, sender: Address) -> Share {
         Share {
             share: value,
             count: 1,
-            id: sender.to_string(),
+            contributors: BTreeMap::from([(sender, 1)]),
+            mac: None,
+            noise: 0.0,
+            noises: 0,
+        }
+    }
+
+    /// Adds a noise contribution to the share, which is only authenticated by a later MAC
+    pub fn add_noise(&mut self, noise: f64) {
+        self.share += noise;
+        self.noise += noise;
+        self.noises += 1;
+    }
+
+    /// Attaches a linear MAC, which is preserved by the aggregation of honest shares
+    pub fn authenticate(&mut self) {
+        self.mac = Some(MAC_KEY * self.share);
+    }
+
+    pub fn verify(&self) -> bool {
+        match self.mac {
+            Some(mac) => (mac - MAC_KEY * self.share).abs() <= 1e-6 * mac.abs().max(1.0),
+            None => false,
         }
     }
 }
@@ -30,23 +69,32 @@ impl fmt::Display for Share {
 }
 
 pub trait AggregatableShares {
-    fn aggregate(self: &Self) -> Share;
+    fn aggregate(&self) -> Share;
 }
 
 impl AggregatableShares for Vec<Share> {
     fn aggregate(&self) -> Share {
-        let mut hasher = DefaultHasher::new();
-        self.iter().for_each(|share| share.id.hash(&mut hasher));
         Share {
             share: self.iter().map(|share| share.share).sum(),
             count: self.iter().map(|share| share.count).sum(),
-            id: hasher.finish().to_string(),
+            contributors: self
+                .iter()
+                .flat_map(|share| share.contributors.iter())
+                .fold(BTreeMap::new(), |mut contributors, (&address, &shares)| {
+                    *contributors.entry(address).or_insert(0) += shares;
+                    contributors
+                }),
+            mac: self.iter().map(|share| share.mac).sum(),
+            noise: self.iter().map(|share| share.noise).sum(),
+            noises: self.iter().map(|share| share.noises).sum(),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use crate::shares::Share;
 
     use super::AggregatableShares;
@@ -61,6 +109,37 @@ mod tests {
 
         assert_eq!(result.share, 6.0);
         assert_eq!(result.count, 3);
-        assert_eq!(result.id, "14127686999214930996");
+        assert_eq!(
+            result.contributors,
+            BTreeMap::from([(123, 1), (125, 1), (1243, 1)])
+        );
+    }
+
+    #[test]
+    fn aggregate_macs() {
+        let mut shares = vec![Share::new(1.0, 123), Share::new(2.0, 125)];
+        shares.iter_mut().for_each(|share| share.authenticate());
+
+        let mut result = shares.aggregate();
+        assert!(result.verify());
+
+        result.share += 10.0;
+        assert!(!result.verify());
+
+        shares.push(Share::new(3.0, 1243));
+        assert_eq!(shares.aggregate().mac, None);
+    }
+
+    #[test]
+    fn noise_is_authenticated_at_the_source() {
+        let mut share = Share::new(1.0, 123);
+        share.add_noise(-2.5);
+        share.authenticate();
+        assert!(share.verify());
+        assert_eq!(share.noise, -2.5);
+
+        // Noise added further up cannot be told apart from tampering
+        share.add_noise(1.0);
+        assert!(!share.verify());
     }
 }
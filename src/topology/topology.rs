@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error, fs};
+
+use crate::{node::NodeRole, tree_node::TreeNode};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TopologyNode {
+    pub role: NodeRole,
+    #[serde(flatten)]
+    pub tree_node: TreeNode,
+}
+
+/// Every node of a tree, with its place in the tree
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Topology {
+    pub nodes: Vec<TopologyNode>,
+}
+
+impl Topology {
+    pub fn from_path(path: &str) -> Result<Topology, Box<dyn Error>> {
+        Topology::parse(fs::read_to_string(path)?.as_str())
+    }
+
+    pub fn parse(content: &str) -> Result<Topology, Box<dyn Error>> {
+        Ok(serde_json::from_str(content)?)
+    }
+
+    /// Checks that the nodes form a tree the manager can run on
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let mut nodes = HashMap::new();
+        for node in &self.nodes {
+            if nodes.insert(node.tree_node.address, node).is_some() {
+                return Err(format!("Node #{} is listed twice", node.tree_node.address).into());
+            }
+        }
+        let queriers = self
+            .nodes
+            .iter()
+            .filter(|node| node.role == NodeRole::Querier)
+            .collect::<Vec<_>>();
+        if queriers.len() != 1 {
+            return Err(format!("Expected a single querier, found {}", queriers.len()).into());
+        }
+        let querier = &queriers[0].tree_node;
+        let depth = self
+            .nodes
+            .iter()
+            .filter(|node| node.role != NodeRole::Querier)
+            .map(|node| node.tree_node.depth)
+            .max()
+            .unwrap_or(0);
+        if depth < 1 {
+            return Err("The tree has no aggregators".into());
+        }
+
+        let mut group_sizes = HashMap::new();
+        for TopologyNode { role, tree_node } in &self.nodes {
+            let address = tree_node.address;
+            let expected_depth = match role {
+                NodeRole::Querier => tree_node.depth,
+                NodeRole::Contributor => 0,
+                NodeRole::LeafAggregator => 1,
+                NodeRole::Aggregator if tree_node.depth > 1 => tree_node.depth,
+                role => return Err(format!("Node #{} cannot have role {}", address, role).into()),
+            };
+            if tree_node.depth != expected_depth {
+                return Err(format!(
+                    "Node #{} is a {} at depth {}",
+                    address, role, tree_node.depth
+                )
+                .into());
+            }
+            if !tree_node.members.contains(&address) {
+                return Err(format!("Node #{} is not a member of its group", address).into());
+            }
+            for member in &tree_node.members {
+                match nodes.get(member) {
+                    Some(peer) if peer.tree_node.members == tree_node.members => {}
+                    _ => {
+                        return Err(format!(
+                            "Node #{} disagrees with member #{} on their group",
+                            address, member
+                        )
+                        .into())
+                    }
+                }
+            }
+            if *role == NodeRole::Aggregator || *role == NodeRole::LeafAggregator {
+                // Groups of a level are expected to share the same size
+                let size = *group_sizes
+                    .entry(tree_node.depth)
+                    .or_insert(tree_node.members.len());
+                if tree_node.members.len() != size {
+                    return Err(format!(
+                        "Groups at depth {} have {} and {} members",
+                        tree_node.depth,
+                        size,
+                        tree_node.members.len()
+                    )
+                    .into());
+                }
+            }
+
+            // Parents and children must point at each other
+            if *role != NodeRole::Querier {
+                let parent_depth = match tree_node.depth {
+                    top if top == depth => querier.depth,
+                    depth => depth + 1,
+                };
+                if tree_node.parents.is_empty() {
+                    return Err(format!("Node #{} has no parents", address).into());
+                }
+                for parent in &tree_node.parents {
+                    match nodes.get(parent) {
+                        Some(peer)
+                            if peer.tree_node.depth == parent_depth
+                                && peer.tree_node.children.contains(&tree_node.members) => {}
+                        _ => {
+                            return Err(format!(
+                                "Node #{} is not a child of its parent #{}",
+                                address, parent
+                            )
+                            .into())
+                        }
+                    }
+                }
+            }
+            for child in tree_node.children.iter().flatten() {
+                match nodes.get(child) {
+                    Some(peer) if peer.tree_node.parents == tree_node.members => {}
+                    _ => {
+                        return Err(format!(
+                            "Node #{} is not a parent of its child #{}",
+                            address, child
+                        )
+                        .into())
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_to_path(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all("outputs")?;
+        fs::write(
+            format!("outputs/{}", filename),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{common::Address, manager::Manager};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct TreeNode {
     pub address: Address,
     pub depth: u8,
@@ -28,7 +30,7 @@ impl TreeNode {
             tabs,
             self.members
                 .iter()
-                .map(|x| format!("#{} ({})", x, manager.nodes.get(&x).unwrap().data().role))
+                .map(|x| format!("#{} ({})", x, manager.nodes.get(x).unwrap().data().role))
                 .collect::<Vec<_>>()
                 .join(", ")
         );